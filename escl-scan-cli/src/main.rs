@@ -7,41 +7,52 @@
 extern crate clap;
 extern crate scan;
 
+use clap::builder::ArgPredicate;
 use clap::{Args, Parser, ValueEnum};
+use scan::auth::Credentials;
+use scan::capscache::CapabilitiesCache;
+use scan::connection::ConnectionOptions;
+use scan::discoveredservice::AddressFamily;
+use scan::discoveryoptions::DiscoveryOptions;
+use scan::pageorder::PageOrder;
+use scan::probebackend::{parse_targets, ProbeBackend};
 use scan::scanner::Scanner;
 use scan::scannerfinder::ScannerFinder;
 use scan::structs::{self};
+use std::io;
 use std::path::PathBuf;
 use std::process::exit;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone, ValueEnum)]
 enum CliColorMode {
     BlackAndWhite,
     Grayscale,
-    RGB,
+    Rgb,
 }
 
-impl From<CliColorMode> for String {
+impl From<CliColorMode> for structs::ColorMode {
     fn from(value: CliColorMode) -> Self {
         match value {
-            CliColorMode::BlackAndWhite => "BlackAndWhite1".to_string(),
-            CliColorMode::Grayscale => "Grayscale8".to_string(),
-            CliColorMode::RGB => "RGB24".to_string(),
+            CliColorMode::BlackAndWhite => structs::ColorMode::BlackAndWhite1,
+            CliColorMode::Grayscale => structs::ColorMode::Grayscale8,
+            CliColorMode::Rgb => structs::ColorMode::Rgb24,
         }
     }
 }
 
 #[derive(Clone, ValueEnum)]
 enum CliOutputFormat {
-    JPG,
-    PDF,
+    Jpg,
+    Pdf,
 }
 
-impl From<CliOutputFormat> for String {
+impl From<CliOutputFormat> for structs::DocumentFormat {
     fn from(value: CliOutputFormat) -> Self {
         match value {
-            CliOutputFormat::JPG => "image/jpeg".to_string(),
-            CliOutputFormat::PDF => "application/pdf".to_string(),
+            CliOutputFormat::Jpg => structs::DocumentFormat::Jpeg,
+            CliOutputFormat::Pdf => structs::DocumentFormat::Pdf,
         }
     }
 }
@@ -72,12 +83,29 @@ enum CliInputSource {
     Platen,
 }
 
-impl From<CliInputSource> for String {
+impl From<CliInputSource> for structs::InputSource {
     fn from(value: CliInputSource) -> Self {
         match value {
-            CliInputSource::Camera => "scan:Camera".into(),
-            CliInputSource::Feeder => "Feeder".into(),
-            CliInputSource::Platen => "Platen".into(),
+            CliInputSource::Camera => structs::InputSource::Camera,
+            CliInputSource::Feeder => structs::InputSource::Feeder,
+            CliInputSource::Platen => structs::InputSource::Platen,
+        }
+    }
+}
+
+#[derive(Clone, ValueEnum)]
+enum CliPageOrder {
+    AsDelivered,
+    FrontsThenBacks,
+    FrontsThenReversedBacks,
+}
+
+impl From<CliPageOrder> for PageOrder {
+    fn from(value: CliPageOrder) -> Self {
+        match value {
+            CliPageOrder::AsDelivered => PageOrder::AsDelivered,
+            CliPageOrder::FrontsThenBacks => PageOrder::FrontsThenBacks,
+            CliPageOrder::FrontsThenReversedBacks => PageOrder::FrontsThenReversedBacks,
         }
     }
 }
@@ -93,16 +121,16 @@ enum CliContentType {
     Auto,
 }
 
-impl From<CliContentType> for String {
+impl From<CliContentType> for structs::ContentType {
     fn from(value: CliContentType) -> Self {
         match value {
-            CliContentType::Photo => "Photo".into(),
-            CliContentType::Text => "Text".into(),
-            CliContentType::TextAndPhoto => "TextAndPhoto".into(),
-            CliContentType::LineArt => "LineArt".into(),
-            CliContentType::Magazine => "Magazine".into(),
-            CliContentType::Halftone => "Halftone".into(),
-            CliContentType::Auto => "Auto".into(),
+            CliContentType::Photo => structs::ContentType::Photo,
+            CliContentType::Text => structs::ContentType::Text,
+            CliContentType::TextAndPhoto => structs::ContentType::TextAndPhoto,
+            CliContentType::LineArt => structs::ContentType::LineArt,
+            CliContentType::Magazine => structs::ContentType::Magazine,
+            CliContentType::Halftone => structs::ContentType::Halftone,
+            CliContentType::Auto => structs::ContentType::Auto,
         }
     }
 }
@@ -113,10 +141,30 @@ struct Cli {
     #[command(flatten)]
     device: DeviceArgs,
 
-    /// Document source
-    #[arg(short = 's', long = "source", value_enum, default_value = "platen")]
+    /// Document source [default: platen, or feeder with --manual-duplex]
+    #[arg(
+        short = 's',
+        long = "source",
+        value_enum,
+        default_value = "platen",
+        default_value_if("manual_duplex", ArgPredicate::IsPresent, "feeder"),
+        hide_default_value = true
+    )]
     input_source: CliInputSource,
 
+    /// Scan both sides of each sheet (feeder only)
+    #[arg(short = 'd', long)]
+    duplex: bool,
+
+    /// Scan both sides with a simplex feeder by scanning the stack twice.
+    /// Implies --source feeder
+    #[arg(long, conflicts_with = "duplex")]
+    manual_duplex: bool,
+
+    /// Order in which the scanner delivers duplex pages
+    #[arg(long, value_enum, default_value = "as-delivered", requires = "duplex")]
+    page_order: CliPageOrder,
+
     /// Input document format
     #[arg(short, long, value_enum, default_value = "a4-portrait")]
     input_format: CliDocumentSize,
@@ -144,6 +192,17 @@ struct Cli {
     /// Content type
     #[arg(short = 't', long = "type", value_enum, default_value = "auto")]
     content_type: CliContentType,
+
+    #[command(flatten)]
+    adjustments: AdjustmentArgs,
+
+    /// Fail instead of falling back to the closest supported settings
+    #[arg(long)]
+    strict: bool,
+
+    /// Seconds to wait for a busy scanner before giving up
+    #[arg(long, default_value = "60")]
+    max_wait: u64,
 }
 
 #[derive(Args)]
@@ -152,6 +211,10 @@ struct DeviceArgs {
     #[arg(long = "host")]
     host: Option<String>,
 
+    /// Select scanner by the URL of its eSCL service, e.g. https://scanner:8443/eSCL
+    #[arg(long, conflicts_with_all = ["host", "https", "port"])]
+    url: Option<String>,
+
     /// Select scanner by device name (can be partial)
     #[arg(long, short)]
     name: Option<String>,
@@ -159,10 +222,223 @@ struct DeviceArgs {
     /// List available scanners
     #[arg(short, long)]
     list: bool,
+
+    /// Also list the full capabilities of each scanner, which takes longer
+    #[arg(long, requires = "list")]
+    capabilities: bool,
+
+    /// Connect to the scanner given with --host using HTTPS
+    #[arg(long)]
+    https: bool,
+
+    /// Port of the scanner given with --host [default: 80, or 443 with --https]
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// Accept self-signed and otherwise invalid certificates
+    #[arg(long)]
+    insecure: bool,
+
+    /// Connect to discovered scanners using HTTPS where they offer it
+    #[arg(long)]
+    prefer_https: bool,
+
+    /// Only trust this PEM encoded certificate
+    #[arg(long, value_name = "PEM_FILE", conflicts_with = "insecure")]
+    certificate: Option<PathBuf>,
+
+    /// User name for scanners that require authentication
+    #[arg(long, env = "ESCL_USER")]
+    user: Option<String>,
+
+    /// Password for scanners that require authentication
+    #[arg(long, env = "ESCL_PASSWORD", hide_env_values = true, requires = "user")]
+    password: Option<String>,
+
+    /// Read credentials from a file containing "user:password"
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["user", "password"])]
+    credentials_file: Option<PathBuf>,
+
+    /// Seconds to look for scanners on the network [default: 5, or until all
+    /// hosts were asked with --probe]
+    #[arg(long, value_name = "SECONDS")]
+    discovery_timeout: Option<u64>,
+
+    /// Connect to discovered scanners using their IPv4 address
+    #[arg(long, conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// Connect to discovered scanners using their IPv6 address
+    #[arg(long)]
+    ipv6: bool,
+
+    /// Always fetch the capabilities from the scanners instead of using cached ones
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Probe these hosts or CIDR ranges (up to /22) instead of using mDNS,
+    /// e.g. 10.0.3.0/24
+    #[arg(long, value_name = "TARGETS", conflicts_with_all = ["host", "url"])]
+    probe: Option<String>,
+}
+
+impl DeviceArgs {
+    fn connection_options(&self) -> Result<ConnectionOptions, String> {
+        let pinned_certificate = match &self.certificate {
+            Some(path) => match std::fs::read(path) {
+                Ok(certificate) => Some(certificate),
+                Err(err) => return Err(format!("Failed to read certificate {path:?}: {err}")),
+            },
+            None => None,
+        };
+
+        Ok(ConnectionOptions {
+            secure: self.https,
+            port: self.port,
+            accept_invalid_certs: self.insecure,
+            pinned_certificate,
+            credentials: self.credentials()?,
+        })
+    }
+
+    fn credentials(&self) -> Result<Option<Credentials>, String> {
+        if let Some(path) = &self.credentials_file {
+            let content = match std::fs::read_to_string(path) {
+                Ok(content) => content,
+                Err(err) => return Err(format!("Failed to read credentials {path:?}: {err}")),
+            };
+
+            return match content.lines().next().and_then(|line| line.split_once(':')) {
+                Some((user, password)) => Ok(Some(Credentials::new(user, password))),
+                None => Err(format!(
+                    "Credentials file {path:?} does not contain user:password"
+                )),
+            };
+        }
+
+        Ok(self
+            .user
+            .as_ref()
+            .map(|user| Credentials::new(user, self.password.as_deref().unwrap_or_default())))
+    }
+
+    fn make_finder(&self, connection_options: ConnectionOptions) -> Result<ScannerFinder, String> {
+        let address_family = match (self.ipv4, self.ipv6) {
+            (true, _) => AddressFamily::Ipv4,
+            (_, true) => AddressFamily::Ipv6,
+            _ => AddressFamily::Any,
+        };
+        let timeout = match (self.discovery_timeout, &self.probe) {
+            (Some(seconds), _) => Duration::from_secs(seconds),
+            (None, Some(_)) => PROBE_TIMEOUT,
+            (None, None) => Duration::from_secs(5),
+        };
+        let mut finder = ScannerFinder::with_connection_options(connection_options.clone())
+            .discovery_options(DiscoveryOptions {
+                timeout,
+                ..Default::default()
+            })
+            .address_family(address_family)
+            .prefer_secure(self.prefer_https);
+
+        // Capabilities hardly ever change, the cache spares asking every
+        // scanner on the network for them
+        let cache = match (self.no_cache, cache_dir()) {
+            (false, Some(dir)) => Some(CapabilitiesCache::on_disk(dir)),
+            (false, None) => Some(CapabilitiesCache::in_memory()),
+            // Probing fetches the capabilities anyway
+            (true, _) if self.probe.is_some() => Some(CapabilitiesCache::in_memory()),
+            (true, _) => None,
+        }
+        .map(Arc::new);
+        if let Some(cache) = &cache {
+            finder = finder.capabilities_cache(Arc::clone(cache));
+        }
+
+        if let Some(targets) = &self.probe {
+            let targets = parse_targets(targets).map_err(|err| err.to_string())?;
+            let mut backend = ProbeBackend::new(targets).connection_options(connection_options);
+            if let Some(cache) = cache {
+                backend = backend.capabilities_cache(cache);
+            }
+            finder = finder.backend(Arc::new(backend));
+        }
+
+        Ok(finder)
+    }
+}
+
+// Probing ends by itself once all hosts were asked, usually much sooner
+const PROBE_TIMEOUT: Duration = Duration::from_secs(600);
+
+fn cache_dir() -> Option<PathBuf> {
+    let cache_home = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+
+    Some(cache_home.join("escl-scan"))
+}
+
+// Image adjustments, the valid ranges are listed in the capabilities (--list --capabilities)
+#[derive(Args)]
+struct AdjustmentArgs {
+    /// Brightness
+    #[arg(long, allow_negative_numbers = true)]
+    brightness: Option<i32>,
+
+    /// Contrast
+    #[arg(long, allow_negative_numbers = true)]
+    contrast: Option<i32>,
+
+    /// Black and white threshold
+    #[arg(long, allow_negative_numbers = true)]
+    threshold: Option<i32>,
+
+    /// Gamma correction
+    #[arg(long, allow_negative_numbers = true)]
+    gamma: Option<i32>,
+
+    /// Sharpening
+    #[arg(long, allow_negative_numbers = true)]
+    sharpen: Option<i32>,
+
+    /// Highlight
+    #[arg(long, allow_negative_numbers = true)]
+    highlight: Option<i32>,
+
+    /// Shadow
+    #[arg(long, allow_negative_numbers = true)]
+    shadow: Option<i32>,
+
+    /// Noise removal
+    #[arg(long, allow_negative_numbers = true)]
+    noise_removal: Option<i32>,
+
+    /// Compression factor
+    #[arg(long, allow_negative_numbers = true)]
+    compression: Option<i32>,
 }
 
-fn list_scanners() {
-    let mut finder = ScannerFinder::new();
+fn list_scanners(device: &DeviceArgs) {
+    let connection_options = match device.connection_options() {
+        Ok(connection_options) => connection_options,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1);
+        }
+    };
+
+    // The mDNS announcements tell enough about the scanners unless asked for
+    // more
+    let finder = match device.make_finder(connection_options) {
+        Ok(finder) => finder,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1);
+        }
+    };
+    let mut finder = finder.lazy(!device.capabilities);
     let scanners = match finder.find(None) {
         Ok(scanners) => scanners,
         Err(err) => {
@@ -171,7 +447,7 @@ fn list_scanners() {
         }
     };
 
-    if scanners.len() == 0 {
+    if scanners.is_empty() {
         println!("No scanners found");
     } else if scanners.len() == 1 {
         println!("Found 1 scanner:");
@@ -185,14 +461,28 @@ fn list_scanners() {
 }
 
 fn get_scanner(cli: &Cli) -> Result<Scanner, String> {
+    let connection_options = cli.device.connection_options()?;
+    if let Some(url) = &cli.device.url {
+        return match Scanner::builder(url)
+            .connection_options(connection_options)
+            .build()
+        {
+            Ok(scanner) => Ok(scanner),
+            Err(err) => Err(format!("{err}")),
+        };
+    }
+
     if let Some(host) = &cli.device.host {
-        return match Scanner::new("Manually Configured", &host, "eSCL") {
+        return match Scanner::with_options("Manually Configured", host, "eSCL", connection_options)
+        {
             Ok(scanner) => Ok(scanner),
             Err(err) => Err(format!("{err}")),
         };
     }
 
-    let scanners = match ScannerFinder::new().find(cli.device.name.as_deref()) {
+    // Only the selected scanner's capabilities are needed
+    let mut finder = cli.device.make_finder(connection_options)?.lazy(true);
+    let scanners = match finder.find(cli.device.name.as_deref()) {
         Ok(scanners) => scanners,
         Err(err) => return Err(err.to_string()),
     };
@@ -201,7 +491,13 @@ fn get_scanner(cli: &Cli) -> Result<Scanner, String> {
         return Ok(scanner.clone());
     }
 
-    return Err("No scanners found".to_string());
+    Err("No scanners found".to_string())
+}
+
+fn prompt_flip_stack() -> bool {
+    println!("Turn the stack over, put it back into the feeder and press Enter to continue");
+    let mut input = String::new();
+    matches!(io::stdin().read_line(&mut input), Ok(read) if read > 0)
 }
 
 fn main() {
@@ -209,44 +505,115 @@ fn main() {
     let args = Cli::parse();
 
     if args.device.list {
-        list_scanners();
+        list_scanners(&args.device);
         exit(0);
     }
 
-    let scanner = match get_scanner(&args) {
+    let mut scanner = match get_scanner(&args) {
         Ok(scanner) => scanner,
         Err(err) => {
             eprintln!("{err}");
             exit(1);
         }
     };
+    scanner.retry_policy.max_wait = Duration::from_secs(args.max_wait);
 
-    if let Err(err) = scanner.get_status() {
-        eprintln!("Failed to get status: {err:?}");
+    // Turning the stack over only works with the feeder
+    if args.manual_duplex && !matches!(args.input_source, CliInputSource::Feeder) {
+        eprintln!("--manual-duplex needs --source feeder");
         exit(1);
     }
 
-    let mut scan_settings = scanner.make_settings();
+    let mut scan_settings = match scanner.make_settings(args.input_source.clone().into()) {
+        Ok(scan_settings) => scan_settings,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1);
+        }
+    };
     scan_settings.x_resolution = args.dpi;
     scan_settings.y_resolution = args.dpi;
     scan_settings.color_mode = args.color.into();
     scan_settings.content_type = args.content_type.into();
     scan_settings.document_format = args.output_format.into();
-    scan_settings.input_source = args.input_source.into();
     scan_settings.scan_regions = args.input_format.into();
-    scan_settings.feed_direction = structs::FeedDirection::ShortEdgeFeed.into();
+    scan_settings.feed_direction = structs::FeedDirection::ShortEdgeFeed;
+    if args.duplex {
+        scan_settings.duplex = Some(true);
+    }
+    scan_settings.brightness = args.adjustments.brightness;
+    scan_settings.contrast = args.adjustments.contrast;
+    scan_settings.threshold = args.adjustments.threshold;
+    scan_settings.gamma = args.adjustments.gamma;
+    scan_settings.sharpen = args.adjustments.sharpen;
+    scan_settings.highlight = args.adjustments.highlight;
+    scan_settings.shadow = args.adjustments.shadow;
+    scan_settings.noise_removal = args.adjustments.noise_removal;
+    scan_settings.compression_factor = args.adjustments.compression;
+
+    if !args.strict {
+        match scanner.negotiate_settings(&mut scan_settings) {
+            Ok(adjustments) => {
+                for adjustment in adjustments {
+                    eprintln!("{adjustment}");
+                }
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                exit(1);
+            }
+        }
+    }
+
+    let capabilities = match scanner.capabilities() {
+        Ok(capabilities) => capabilities,
+        Err(err) => {
+            eprintln!("{err}");
+            exit(1);
+        }
+    };
+    let violations = scan_settings.validate(capabilities);
+    if !violations.is_empty() {
+        eprintln!("The scanner does not support the requested settings:");
+        for violation in violations {
+            eprintln!("- {violation}");
+        }
+        exit(1);
+    }
+
+    if let Err(err) = scanner.preflight(&scan_settings) {
+        eprintln!("{err}");
+        exit(1);
+    }
 
     let destination_file_name = if let Some(base_path) = args.output_base_path {
         base_path
-            .join(args.output_file_name)
+            .join(&args.output_file_name)
             .to_str()
             .expect("Path is printable")
             .to_string()
     } else {
-        args.output_file_name
+        args.output_file_name.clone()
+    };
+    // The scanner may not offer the requested format
+    let destination_file_name = scan_settings
+        .document_format
+        .file_name(&destination_file_name);
+    if !destination_file_name.ends_with(&args.output_file_name) {
+        eprintln!("Saving as {destination_file_name}");
+    }
+
+    let result = if args.manual_duplex {
+        scanner.scan_manual_duplex(&scan_settings, &destination_file_name, prompt_flip_stack)
+    } else {
+        scanner.scan(
+            &scan_settings,
+            args.page_order.into(),
+            &destination_file_name,
+        )
     };
 
-    if let Err(err) = scanner.scan(&scan_settings, &destination_file_name) {
+    if let Err(err) = result {
         eprintln!("Failed to scan: {err:?}");
         exit(1);
     }
@@ -0,0 +1,137 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::{
+    auth::{self, Credentials},
+    retry::RetryPolicy,
+    scanjob::job_uuid,
+    scanner::Scanner,
+    scannererror::{ErrorCode, ScannerError},
+    structs,
+};
+
+/// Non-blocking counterpart of `ScanJob`, returned by
+/// `AsyncScanner::create_scan_job()`.
+#[derive(Debug)]
+pub struct AsyncScanJob {
+    client: reqwest::Client,
+    job_url: String,
+    retry_policy: RetryPolicy,
+    credentials: Option<Credentials>,
+    pages_downloaded: usize,
+    finished: bool,
+}
+
+impl AsyncScanJob {
+    pub(crate) fn new(
+        client: reqwest::Client,
+        job_url: String,
+        retry_policy: RetryPolicy,
+        credentials: Option<Credentials>,
+    ) -> AsyncScanJob {
+        AsyncScanJob {
+            client,
+            job_url,
+            retry_policy,
+            credentials,
+            pages_downloaded: 0,
+            finished: false,
+        }
+    }
+
+    /// See `ScanJob::url()`.
+    pub fn url(&self) -> &str {
+        &self.job_url
+    }
+
+    /// See `ScanJob::uuid()`.
+    pub fn uuid(&self) -> Option<&str> {
+        job_uuid(&self.job_url)
+    }
+
+    pub fn pages_downloaded(&self) -> usize {
+        self.pages_downloaded
+    }
+
+    /// Whether the scanner reported that there are no more pages.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Downloads the next page. Returns None once the scanner has no more
+    /// pages for this job.
+    pub async fn next_page(&mut self) -> Result<Option<Vec<u8>>, ScannerError> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        // Scanners answer 503 while the page is still being scanned
+        let response = self
+            .retry_policy
+            .send_async(self.credentials.as_ref(), || {
+                self.client.get(format!("{}/NextDocument", self.job_url))
+            })
+            .await?;
+        if response.status() == 404 {
+            log::info!("There is no page {}, we're done", self.pages_downloaded + 1);
+            self.finished = true;
+            return Ok(None);
+        }
+
+        if response.status().is_client_error() || response.status().is_server_error() {
+            let status = response.status();
+            return Err(Scanner::make_status_error(status, &response.text().await?));
+        }
+
+        let page = response.bytes().await?.to_vec();
+        self.pages_downloaded += 1;
+        log::info!(
+            "Downloaded page {} ({} bytes)",
+            self.pages_downloaded,
+            page.len()
+        );
+        Ok(Some(page))
+    }
+
+    /// See `ScanJob::image_info()`.
+    pub async fn image_info(&self) -> Result<structs::ScanImageInfo, ScannerError> {
+        let response = auth::send_async(self.credentials.as_ref(), || {
+            self.client.get(format!("{}/ScanImageInfo", self.job_url))
+        })
+        .await?;
+        if response.status().is_client_error() || response.status().is_server_error() {
+            let status = response.status();
+            return Err(Scanner::make_status_error(status, &response.text().await?));
+        }
+
+        let response_string = response.text().await?;
+        log::debug!("> ScanImageInfo: {response_string}");
+        Ok(serde_xml_rs::from_str(&response_string)?)
+    }
+
+    /// See `ScanJob::cancel()`.
+    pub async fn cancel(self) -> Result<(), ScannerError> {
+        log::info!("Cancelling scan job {}", self.job_url);
+        let response = auth::send_async(self.credentials.as_ref(), || {
+            self.client.delete(&self.job_url)
+        })
+        .await?;
+        if response.status().is_client_error() || response.status().is_server_error() {
+            let status = response.status();
+            return Err(ScannerError {
+                code: ErrorCode::NetworkError,
+                message: format!(
+                    "Failed to cancel job {}: Status Code: {:?}, Text: {}",
+                    self.job_url,
+                    status,
+                    response.text().await?
+                ),
+            });
+        }
+
+        Ok(())
+    }
+}
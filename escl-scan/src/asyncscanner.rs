@@ -0,0 +1,400 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{fmt::Display, time::Instant};
+
+use crate::{
+    asyncscanjob::AsyncScanJob,
+    auth::{self, Credentials},
+    capscache::CapabilitiesCache,
+    connection::ConnectionOptions,
+    pageorder::PageOrder,
+    retry::RetryPolicy,
+    scanner::Scanner,
+    scannerbuilder::parse_base_url,
+    scannererror::{ErrorCode, ScannerError},
+    structs::{self},
+};
+
+/// Non-blocking counterpart of `Scanner` for use within async runtimes like
+/// tokio. Pages are kept in memory instead of being written to files.
+#[derive(Clone, Debug)]
+pub struct AsyncScanner {
+    pub base_url: String,
+    pub device_name: String,
+    pub capabilities: structs::ScannerCapabilities,
+    pub retry_policy: RetryPolicy,
+    credentials: Option<Credentials>,
+    client: reqwest::Client,
+}
+
+impl AsyncScanner {
+    async fn get_capabilities(
+        client: &reqwest::Client,
+        credentials: Option<&Credentials>,
+        base_url: &str,
+        cache: Option<&CapabilitiesCache>,
+    ) -> Result<structs::ScannerCapabilities, ScannerError> {
+        let response = auth::send_async(credentials, || {
+            client.get(format!("{}/ScannerCapabilities", base_url))
+        })
+        .await?;
+        let response_string = response.text().await?;
+        log::debug!("> Capabilities: {response_string}");
+
+        match cache {
+            Some(cache) => cache.insert(&response_string),
+            None => Ok(serde_xml_rs::from_str(&response_string)?),
+        }
+    }
+
+    pub async fn new(
+        device_name: &str,
+        ip_or_host: &str,
+        resource_root: &str,
+    ) -> Result<AsyncScanner, ScannerError> {
+        Self::with_options(
+            device_name,
+            ip_or_host,
+            resource_root,
+            ConnectionOptions::default(),
+        )
+        .await
+    }
+
+    /// See `Scanner::with_options()`.
+    pub async fn with_options(
+        device_name: &str,
+        ip_or_host: &str,
+        resource_root: &str,
+        connection_options: ConnectionOptions,
+    ) -> Result<AsyncScanner, ScannerError> {
+        let base_url = connection_options.base_url(ip_or_host, resource_root);
+        let client = connection_options.make_async_client()?;
+        Self::connect(
+            &base_url,
+            Some(device_name),
+            client,
+            connection_options.credentials,
+        )
+        .await
+    }
+
+    /// See `Scanner::from_url()`.
+    pub async fn from_url(base_url: &str) -> Result<AsyncScanner, ScannerError> {
+        let url = parse_base_url(base_url)?;
+        let connection_options = ConnectionOptions {
+            secure: url.scheme() == "https",
+            port: url.port_or_known_default(),
+            ..Default::default()
+        };
+        Self::connect(
+            base_url,
+            None,
+            connection_options.make_async_client()?,
+            None,
+        )
+        .await
+    }
+
+    /// Connects to the eSCL service at the given URL using a preconfigured
+    /// client, e.g. with timeouts, a proxy or default headers.
+    pub async fn with_client(
+        base_url: &str,
+        client: reqwest::Client,
+        credentials: Option<Credentials>,
+    ) -> Result<AsyncScanner, ScannerError> {
+        Self::connect(base_url, None, client, credentials).await
+    }
+
+    async fn connect(
+        base_url: &str,
+        device_name: Option<&str>,
+        client: reqwest::Client,
+        credentials: Option<Credentials>,
+    ) -> Result<AsyncScanner, ScannerError> {
+        let url = parse_base_url(base_url)?;
+        let base_url = base_url.trim_end_matches('/').to_string();
+        let capabilities =
+            Self::get_capabilities(&client, credentials.as_ref(), &base_url, None).await?;
+
+        Ok(AsyncScanner {
+            device_name: device_name
+                .or(url.host_str())
+                .unwrap_or_default()
+                .to_string(),
+            base_url,
+            capabilities,
+            retry_policy: RetryPolicy::default(),
+            credentials,
+            client,
+        })
+    }
+
+    pub async fn get_status(&self) -> Result<structs::ScannerState, ScannerError> {
+        let scanner_status = self.get_full_status().await?;
+        log::info!("Scanner state: {}", scanner_status.state);
+
+        Ok(scanner_status.state)
+    }
+
+    pub async fn get_full_status(&self) -> Result<structs::ScannerStatus, ScannerError> {
+        log::info!("Getting scanner status");
+        let response = auth::send_async(self.credentials.as_ref(), || {
+            self.client.get(format!("{}/ScannerStatus", self.base_url))
+        })
+        .await?;
+        let response_string = response.text().await?;
+        log::debug!("ScannerStatus: {:?}", response_string);
+
+        let scanner_status: structs::ScannerStatus = serde_xml_rs::from_str(&response_string)?;
+        Ok(scanner_status)
+    }
+
+    /// See `Scanner::preflight()`.
+    pub async fn preflight(
+        &self,
+        scan_settings: &structs::ScanSettings,
+    ) -> Result<(), ScannerError> {
+        let start = Instant::now();
+        while let Some(delay) = Scanner::preflight_delay(
+            &self.retry_policy,
+            &self.get_full_status().await?,
+            &scan_settings.input_source,
+            start,
+        )? {
+            tokio::time::sleep(delay).await;
+        }
+
+        Ok(())
+    }
+
+    /// Converts a `Scanner`, e.g. one found by `ScannerFinder`. Capabilities
+    /// of a lazily built scanner are fetched with the async client.
+    ///
+    /// A client passed to `ScannerBuilder::client()` cannot be carried over,
+    /// a new one is made from the connection options.
+    pub async fn from_scanner(scanner: Scanner) -> Result<AsyncScanner, ScannerError> {
+        let client = scanner.connection_options.make_async_client()?;
+        let credentials = scanner.connection_options.credentials;
+        let cached = match (&scanner.capabilities_cache, &scanner.uuid) {
+            (Some(cache), Some(uuid)) => cache.get(uuid, scanner.version.as_deref()),
+            _ => None,
+        };
+        let capabilities = match scanner.capabilities.into_inner().or(cached) {
+            Some(capabilities) => capabilities,
+            None => {
+                log::info!("Getting capabilities of {}", scanner.device_name);
+                Self::get_capabilities(
+                    &client,
+                    credentials.as_ref(),
+                    &scanner.base_url,
+                    scanner.capabilities_cache.as_deref(),
+                )
+                .await?
+            }
+        };
+
+        Ok(AsyncScanner {
+            base_url: scanner.base_url,
+            device_name: scanner.device_name,
+            capabilities,
+            retry_policy: scanner.retry_policy,
+            credentials,
+            client,
+        })
+    }
+
+    /// Starts a scan job. Its pages must be fetched until
+    /// `AsyncScanJob::next_page()` returns None, or the job cancelled.
+    pub async fn create_scan_job(
+        &self,
+        scan_settings: &structs::ScanSettings,
+    ) -> Result<AsyncScanJob, ScannerError> {
+        let request_body = Scanner::make_scan_request_body(scan_settings, &self.capabilities)?;
+
+        log::info!("Sending scan request with settings: {:?}", scan_settings);
+        let response = self
+            .retry_policy
+            .send_async(self.credentials.as_ref(), || {
+                self.client
+                    .post(format!("{}/ScanJobs", &self.base_url))
+                    .body(request_body.clone())
+            })
+            .await?;
+        log::debug!("> ScanJobs: {response:#?}");
+
+        if response.status().is_client_error() || response.status().is_server_error() {
+            let status = response.status();
+            return Err(Scanner::make_status_error(status, &response.text().await?));
+        }
+
+        Ok(AsyncScanJob::new(
+            self.client.clone(),
+            Scanner::make_job_url(&self.base_url, response.headers())?,
+            self.retry_policy.clone(),
+            self.credentials.clone(),
+        ))
+    }
+
+    /// Runs a scan job to completion and returns its pages in reading order.
+    /// `page_order` tells how a duplex feeder delivers them.
+    pub async fn scan(
+        &self,
+        scan_settings: &structs::ScanSettings,
+        page_order: PageOrder,
+    ) -> Result<Vec<Vec<u8>>, ScannerError> {
+        self.preflight(scan_settings).await?;
+        let mut job = self.create_scan_job(scan_settings).await?;
+
+        // Downloading until there are no more pages is what makes the scanner
+        // consider the job done, see Scanner::scan_pages().
+        let mut pages = vec![];
+        loop {
+            match job.next_page().await {
+                Ok(Some(page)) => pages.push(page),
+                Ok(None) => break,
+                Err(err) => {
+                    // Free the scanner instead of leaving it to its job timeout
+                    if let Err(cancel_err) = job.cancel().await {
+                        log::warn!("Failed to cancel the scan job: {cancel_err}");
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        if pages.is_empty() {
+            log::error!("Scanner has no pages available for download at all");
+            return Err(ScannerError {
+                code: ErrorCode::NoMorePages,
+                message: String::new(),
+            });
+        }
+
+        Ok(page_order.arrange(pages))
+    }
+}
+
+impl Display for AsyncScanner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}
+- URL: {}
+- Capabilities: {:#?}",
+            self.device_name, self.base_url, self.capabilities
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use crate::{
+        asyncscanner::AsyncScanner,
+        pageorder::PageOrder,
+        scanner::Scanner,
+        scannererror::ErrorCode,
+        structs::{InputSource, ScannerState},
+        testutil::{failing_scanner, fake_scanner, scan_settings},
+    };
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn async_scan() {
+        let server = fake_scanner(vec![vec![b"1".to_vec(), b"2".to_vec(), b"1 back".to_vec()]]);
+        let pages = block_on(async {
+            let scanner = AsyncScanner::from_url(&format!("{}/eSCL", server.url)).await?;
+            assert!(scanner.capabilities.make_and_model == "Brother MFC-L2710DW series");
+            assert!(scanner.get_status().await? == ScannerState::Idle);
+            scanner
+                .scan(
+                    &scan_settings(InputSource::Feeder),
+                    PageOrder::FrontsThenBacks,
+                )
+                .await
+        })
+        .unwrap();
+        assert!(pages == vec![b"1".to_vec(), b"1 back".to_vec(), b"2".to_vec()]);
+
+        let requests = server.requests();
+        assert!(requests.contains(&"POST /eSCL/ScanJobs".to_string()));
+        assert!(
+            requests
+                .iter()
+                .filter(|request| request.ends_with("/NextDocument"))
+                .count()
+                == 4
+        );
+    }
+
+    #[test]
+    fn async_scan_cancels_failed_job() {
+        let server = failing_scanner();
+        let err = block_on(async {
+            let scanner = AsyncScanner::from_url(&format!("{}/eSCL", server.url))
+                .await
+                .unwrap();
+            scanner
+                .scan(&scan_settings(InputSource::Platen), PageOrder::AsDelivered)
+                .await
+                .unwrap_err()
+        });
+        assert!(matches!(err.code, ErrorCode::NetworkError));
+        assert!(server
+            .requests()
+            .contains(&"DELETE /eSCL/ScanJobs/1".to_string()));
+    }
+
+    #[test]
+    fn async_job() {
+        let server = fake_scanner(vec![vec![b"1".to_vec()]]);
+        block_on(async {
+            let scanner = AsyncScanner::from_url(&format!("{}/eSCL", server.url))
+                .await
+                .unwrap();
+            let mut job = scanner
+                .create_scan_job(&scan_settings(InputSource::Platen))
+                .await
+                .unwrap();
+            assert!(job.url() == format!("{}/eSCL/ScanJobs/1", server.url));
+            assert!(job.uuid() == Some("1"));
+
+            assert!(job.next_page().await.unwrap() == Some(b"1".to_vec()));
+            let info = job.image_info().await.unwrap();
+            assert!(info.actual_width == Some(2480));
+            assert!(job.pages_downloaded() == 1);
+            job.cancel().await.unwrap();
+        });
+        assert!(server
+            .requests()
+            .contains(&"DELETE /eSCL/ScanJobs/1".to_string()));
+    }
+
+    #[test]
+    fn from_lazy_scanner() {
+        let server = fake_scanner(vec![]);
+        let scanner = Scanner::builder(&format!("{}/eSCL", server.url))
+            .lazy(true)
+            .build()
+            .unwrap();
+        assert!(server.requests().is_empty());
+
+        // Fetched with the async client, the blocking one would panic here
+        let scanner = block_on(AsyncScanner::from_scanner(scanner)).unwrap();
+        assert!(scanner.capabilities.make_and_model == "Brother MFC-L2710DW series");
+        assert!(server.requests() == vec!["GET /eSCL/ScannerCapabilities".to_string()]);
+    }
+}
@@ -0,0 +1,610 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE},
+    Method, StatusCode, Url,
+};
+
+use crate::scannererror::{ErrorCode, ScannerError};
+
+/// User name and password for scanners that require authentication. Basic
+/// and Digest authentication are supported, whichever the scanner asks for.
+///
+/// Once a scanner has challenged a request, later requests to it are
+/// authenticated right away. Clones share that state.
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+    // Keyed by the origin of the scanner
+    schemes: Arc<Mutex<HashMap<String, Scheme>>>,
+}
+
+impl Credentials {
+    pub fn new(username: &str, password: &str) -> Credentials {
+        Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+            schemes: Arc::default(),
+        }
+    }
+
+    // Authorization for a scanner that challenged an earlier request
+    fn cached_authorization(
+        &self,
+        method: &Method,
+        url: &Url,
+    ) -> Result<Option<Authorization>, ScannerError> {
+        let mut schemes = self.schemes.lock().unwrap();
+        match schemes.get_mut(&url.origin().ascii_serialization()) {
+            None => Ok(None),
+            Some(Scheme::Basic) => Ok(Some(Authorization::Basic)),
+            Some(Scheme::Digest { challenge, nc }) => {
+                *nc += 1;
+                let digest = self.digest_authorization(
+                    challenge,
+                    method.as_str(),
+                    &request_uri(url),
+                    *nc,
+                    &make_cnonce(),
+                )?;
+                Ok(Some(Authorization::Digest(digest)))
+            }
+        }
+    }
+
+    // Remembers the challenge for the following requests. A request that
+    // was already authenticated is only repeated if the scanner merely
+    // renewed its nonce.
+    fn remember_challenge(
+        &self,
+        headers: &HeaderMap,
+        url: &Url,
+        authenticated: bool,
+    ) -> Result<(), ScannerError> {
+        let scheme = match Scheme::from_challenge(headers) {
+            Ok(scheme) if !authenticated || scheme.is_stale() => scheme,
+            Ok(_) => {
+                self.forget(url);
+                return Err(make_authentication_error(Some(self)));
+            }
+            Err(err) => {
+                self.forget(url);
+                return Err(err);
+            }
+        };
+        self.schemes
+            .lock()
+            .unwrap()
+            .insert(url.origin().ascii_serialization(), scheme);
+        Ok(())
+    }
+
+    fn forget(&self, url: &Url) {
+        self.schemes
+            .lock()
+            .unwrap()
+            .remove(&url.origin().ascii_serialization());
+    }
+
+    fn digest_authorization(
+        &self,
+        challenge: &str,
+        method: &str,
+        uri: &str,
+        nc: u32,
+        cnonce: &str,
+    ) -> Result<String, ScannerError> {
+        let params = parse_challenge_params(challenge);
+        let param = |name: &str| find_param(&params, name);
+
+        let realm = param("realm").unwrap_or_default();
+        let nonce = param("nonce").ok_or_else(|| ScannerError {
+            code: ErrorCode::AuthenticationFailed,
+            message: "Digest challenge without nonce".to_string(),
+        })?;
+        let algorithm = param("algorithm").unwrap_or("MD5");
+        let qop = param("qop").map(|qop| qop.split(',').map(str::trim).any(|qop| qop == "auth"));
+        let nc = format!("{nc:08x}");
+
+        let mut ha1 = md5_hex(&format!("{}:{}:{}", self.username, realm, self.password));
+        match algorithm.to_ascii_uppercase().as_str() {
+            "MD5" => {}
+            "MD5-SESS" => ha1 = md5_hex(&format!("{ha1}:{nonce}:{cnonce}")),
+            _ => {
+                return Err(ScannerError {
+                    code: ErrorCode::AuthenticationFailed,
+                    message: format!("Unsupported digest algorithm {algorithm}"),
+                })
+            }
+        }
+        let ha2 = md5_hex(&format!("{method}:{uri}"));
+
+        let mut authorization = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}",
+            self.username, realm, nonce, uri, algorithm
+        );
+        match qop {
+            Some(true) => {
+                let response = md5_hex(&format!("{ha1}:{nonce}:{nc}:{cnonce}:auth:{ha2}"));
+                authorization +=
+                    &format!(", qop=auth, nc={nc}, cnonce=\"{cnonce}\", response=\"{response}\"");
+            }
+            // Only auth-int is offered, which would require hashing the body
+            Some(false) => {
+                return Err(ScannerError {
+                    code: ErrorCode::AuthenticationFailed,
+                    message: "Unsupported digest quality of protection".to_string(),
+                })
+            }
+            None => {
+                let response = md5_hex(&format!("{ha1}:{nonce}:{ha2}"));
+                authorization += &format!(", response=\"{response}\"");
+            }
+        }
+        if let Some(opaque) = param("opaque") {
+            authorization += &format!(", opaque=\"{opaque}\"");
+        }
+
+        Ok(authorization)
+    }
+}
+
+impl PartialEq for Credentials {
+    fn eq(&self, other: &Self) -> bool {
+        self.username == other.username && self.password == other.password
+    }
+}
+
+// The password must not end up in logs
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+enum Scheme {
+    Basic,
+    /// Parameters of the challenge and the number of requests made with its
+    /// nonce so far
+    Digest {
+        challenge: String,
+        nc: u32,
+    },
+}
+
+impl Scheme {
+    // Picks the strongest challenge the scanner sent
+    fn from_challenge(headers: &HeaderMap) -> Result<Scheme, ScannerError> {
+        let challenges: Vec<&str> = headers
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+
+        if let Some(challenge) = challenges.iter().find_map(|c| strip_scheme(c, "Digest")) {
+            return Ok(Scheme::Digest {
+                challenge: challenge.to_string(),
+                nc: 0,
+            });
+        }
+
+        if challenges
+            .iter()
+            .any(|c| strip_scheme(c, "Basic").is_some())
+        {
+            return Ok(Scheme::Basic);
+        }
+
+        Err(ScannerError {
+            code: ErrorCode::AuthenticationFailed,
+            message: format!(
+                "Unsupported authentication scheme: {}",
+                challenges.join(", ")
+            ),
+        })
+    }
+
+    fn is_stale(&self) -> bool {
+        match self {
+            Scheme::Basic => false,
+            Scheme::Digest { challenge, .. } => {
+                find_param(&parse_challenge_params(challenge), "stale")
+                    .is_some_and(|stale| stale.eq_ignore_ascii_case("true"))
+            }
+        }
+    }
+}
+
+enum Authorization {
+    Basic,
+    Digest(String),
+}
+
+fn make_authentication_error(credentials: Option<&Credentials>) -> ScannerError {
+    ScannerError {
+        code: ErrorCode::AuthenticationFailed,
+        message: match credentials {
+            Some(credentials) => format!("Credentials for {} were rejected", credentials.username),
+            None => "The scanner requires credentials".to_string(),
+        },
+    }
+}
+
+// Decides how to authenticate one request and whether to repeat it after a
+// challenge. Shared by send() and send_async(), which only do the I/O.
+struct AuthFlow<'a> {
+    credentials: Option<&'a Credentials>,
+    method: Method,
+    url: Url,
+    authenticated: bool,
+    renewed: bool,
+}
+
+impl<'a> AuthFlow<'a> {
+    fn new(credentials: Option<&'a Credentials>, method: &Method, url: &Url) -> AuthFlow<'a> {
+        AuthFlow {
+            credentials,
+            method: method.clone(),
+            url: url.clone(),
+            authenticated: false,
+            renewed: false,
+        }
+    }
+
+    // Authorization for the next attempt, if the scanner challenged before
+    fn authorization(&mut self) -> Result<Option<Authorization>, ScannerError> {
+        let authorization = match self.credentials {
+            Some(credentials) => credentials.cached_authorization(&self.method, &self.url)?,
+            None => None,
+        };
+        self.authenticated = authorization.is_some();
+        Ok(authorization)
+    }
+
+    // Whether the request has to be sent again after the given response
+    fn retry(&mut self, status: StatusCode, headers: &HeaderMap) -> Result<bool, ScannerError> {
+        if status != StatusCode::UNAUTHORIZED {
+            return Ok(false);
+        }
+
+        let credentials = self
+            .credentials
+            .ok_or_else(|| make_authentication_error(None))?;
+        // A stale nonce is renewed once, a second rejection is final
+        if self.authenticated && self.renewed {
+            credentials.forget(&self.url);
+            return Err(make_authentication_error(Some(credentials)));
+        }
+        self.renewed = self.authenticated;
+        credentials.remember_challenge(headers, &self.url, self.authenticated)?;
+        Ok(true)
+    }
+}
+
+/// Sends the request built by `make_request`, answering an authentication
+/// challenge with the given credentials.
+pub(crate) fn send<F>(
+    credentials: Option<&Credentials>,
+    make_request: F,
+) -> Result<reqwest::blocking::Response, ScannerError>
+where
+    F: Fn() -> reqwest::blocking::RequestBuilder,
+{
+    let authorize = |authorization: Option<Authorization>| -> Result<_, ScannerError> {
+        Ok(match (credentials, authorization) {
+            (Some(credentials), Some(Authorization::Basic)) => {
+                make_request().basic_auth(&credentials.username, Some(&credentials.password))
+            }
+            (_, Some(Authorization::Digest(digest))) => {
+                make_request().header(AUTHORIZATION, header_value(&digest)?)
+            }
+            _ => make_request(),
+        })
+    };
+
+    let request = make_request().build()?;
+    let mut flow = AuthFlow::new(credentials, request.method(), request.url());
+    loop {
+        let response = authorize(flow.authorization()?)?.send()?;
+        if !flow.retry(response.status(), response.headers())? {
+            return Ok(response);
+        }
+    }
+}
+
+/// See `send()`.
+#[cfg(feature = "async")]
+pub(crate) async fn send_async<F>(
+    credentials: Option<&Credentials>,
+    make_request: F,
+) -> Result<reqwest::Response, ScannerError>
+where
+    F: Fn() -> reqwest::RequestBuilder,
+{
+    let authorize = |authorization: Option<Authorization>| -> Result<_, ScannerError> {
+        Ok(match (credentials, authorization) {
+            (Some(credentials), Some(Authorization::Basic)) => {
+                make_request().basic_auth(&credentials.username, Some(&credentials.password))
+            }
+            (_, Some(Authorization::Digest(digest))) => {
+                make_request().header(AUTHORIZATION, header_value(&digest)?)
+            }
+            _ => make_request(),
+        })
+    };
+
+    let request = make_request().build()?;
+    let mut flow = AuthFlow::new(credentials, request.method(), request.url());
+    loop {
+        let response = authorize(flow.authorization()?)?.send().await?;
+        if !flow.retry(response.status(), response.headers())? {
+            return Ok(response);
+        }
+    }
+}
+
+fn request_uri(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, ScannerError> {
+    HeaderValue::from_str(value).map_err(|err| ScannerError {
+        code: ErrorCode::AuthenticationFailed,
+        message: format!("Invalid characters in credentials: {err}"),
+    })
+}
+
+// Returns the parameters of a challenge like `Digest realm="x", nonce="y"`
+fn strip_scheme<'a>(challenge: &'a str, scheme: &str) -> Option<&'a str> {
+    let challenge = challenge.trim_start();
+    let (challenge_scheme, params) = challenge.split_once(' ').unwrap_or((challenge, ""));
+    challenge_scheme
+        .eq_ignore_ascii_case(scheme)
+        .then_some(params)
+}
+
+fn parse_challenge_params(params: &str) -> Vec<(String, String)> {
+    let mut parsed = vec![];
+    let mut rest = params.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+        let value = value.trim_start();
+        let (value, remainder) = match value.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                None => (quoted, ""),
+            },
+            None => match value.find(',') {
+                Some(end) => (&value[..end], &value[end..]),
+                None => (value, ""),
+            },
+        };
+        parsed.push((key, value.trim().to_string()));
+        rest = remainder.trim_start().trim_start_matches(',');
+    }
+
+    parsed
+}
+
+fn find_param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn md5_hex(value: &str) -> String {
+    format!("{:x}", md5::compute(value))
+}
+
+// Only needs to be unique, not unpredictable
+fn make_cnonce() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    md5_hex(&format!(
+        "{now}:{}",
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))[..16]
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{
+        header::{HeaderMap, HeaderValue, WWW_AUTHENTICATE},
+        Method, StatusCode, Url,
+    };
+
+    use crate::{
+        auth::{self, parse_challenge_params, strip_scheme, AuthFlow, Authorization, Credentials},
+        scannererror::ErrorCode,
+        testutil::{TestResponse, TestServer},
+    };
+
+    fn get(server: &TestServer, credentials: &Credentials) -> Result<u16, ErrorCode> {
+        let client = reqwest::blocking::Client::new();
+        auth::send(Some(credentials), || {
+            client.get(format!("{}/eSCL/ScannerStatus", server.url))
+        })
+        .map(|response| response.status().as_u16())
+        .map_err(|err| err.code)
+    }
+
+    #[test]
+    fn challenge_parsing() {
+        let challenge = r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", algorithm=MD5"#;
+        let params = parse_challenge_params(strip_scheme(challenge, "digest").unwrap());
+        assert!(
+            params
+                == vec![
+                    ("realm".to_string(), "testrealm@host.com".to_string()),
+                    ("qop".to_string(), "auth,auth-int".to_string()),
+                    (
+                        "nonce".to_string(),
+                        "dcd98b7102dd2f0e8b11d0f600bfb0c093".to_string()
+                    ),
+                    ("algorithm".to_string(), "MD5".to_string()),
+                ]
+        );
+        assert!(strip_scheme(challenge, "Basic").is_none());
+    }
+
+    #[test]
+    fn digest_response() {
+        // Example from RFC 2617, section 3.5
+        let credentials = Credentials::new("Mufasa", "Circle Of Life");
+        let challenge = r#"realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#;
+        let authorization = credentials
+            .digest_authorization(challenge, "GET", "/dir/index.html", 1, "0a4f113b")
+            .expect("challenge is supported");
+        assert!(authorization.contains(r#"response="6629fae49393a05397450978507c4ef1""#));
+        assert!(authorization.contains("qop=auth, nc=00000001"));
+        assert!(authorization.contains(r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#));
+        assert!(!format!("{credentials:?}").contains("Circle"));
+    }
+
+    #[test]
+    fn stale_nonce_is_renewed_once() {
+        let challenge = |stale: bool| {
+            let mut headers = HeaderMap::new();
+            let value = format!(r#"Digest realm="scanner", nonce="n", stale={stale}"#);
+            headers.insert(WWW_AUTHENTICATE, HeaderValue::from_str(&value).unwrap());
+            headers
+        };
+        let credentials = Credentials::new("user", "pass");
+        let url = Url::parse("http://scanner/eSCL/ScannerStatus").unwrap();
+
+        let mut flow = AuthFlow::new(Some(&credentials), &Method::GET, &url);
+        assert!(flow.authorization().unwrap().is_none());
+        assert!(flow
+            .retry(StatusCode::UNAUTHORIZED, &challenge(false))
+            .unwrap());
+        assert!(matches!(
+            flow.authorization().unwrap(),
+            Some(Authorization::Digest(_))
+        ));
+        assert!(flow
+            .retry(StatusCode::UNAUTHORIZED, &challenge(true))
+            .unwrap());
+        assert!(flow.authorization().unwrap().is_some());
+        assert!(flow
+            .retry(StatusCode::UNAUTHORIZED, &challenge(true))
+            .is_err());
+
+        // The rejected challenge is not reused, nor retried without being stale
+        let mut flow = AuthFlow::new(Some(&credentials), &Method::GET, &url);
+        assert!(flow.authorization().unwrap().is_none());
+        assert!(flow
+            .retry(StatusCode::UNAUTHORIZED, &challenge(false))
+            .unwrap());
+        assert!(flow.authorization().unwrap().is_some());
+        assert!(flow
+            .retry(StatusCode::UNAUTHORIZED, &challenge(false))
+            .is_err());
+        assert!(!flow.retry(StatusCode::OK, &HeaderMap::new()).unwrap());
+
+        let mut flow = AuthFlow::new(None, &Method::GET, &url);
+        assert!(flow
+            .retry(StatusCode::UNAUTHORIZED, &challenge(false))
+            .is_err());
+    }
+
+    #[test]
+    fn basic_credentials_are_sent_preemptively() {
+        // user:pass
+        let server = TestServer::start(|request| match request.header("Authorization") {
+            Some("Basic dXNlcjpwYXNz") => TestResponse::ok("status"),
+            _ => TestResponse::status(401).header("WWW-Authenticate", r#"Basic realm="scanner""#),
+        });
+
+        let credentials = Credentials::new("user", "pass");
+        for _ in 0..3 {
+            assert!(get(&server, &credentials).unwrap() == 200);
+        }
+        // Only the first request was challenged
+        assert!(server.requests().len() == 4);
+
+        let wrong_credentials = Credentials::new("user", "wrong");
+        assert!(matches!(
+            get(&server, &wrong_credentials),
+            Err(ErrorCode::AuthenticationFailed)
+        ));
+        assert!(matches!(
+            get(&server, &wrong_credentials),
+            Err(ErrorCode::AuthenticationFailed)
+        ));
+        assert!(server.requests().len() == 8);
+    }
+
+    #[test]
+    fn digest_challenge_is_reused() {
+        let mut nonce = 1;
+        let mut last_nc = 0;
+        let server = TestServer::start(move |request| {
+            let challenge = |stale: bool| {
+                TestResponse::status(401).header(
+                    "WWW-Authenticate",
+                    &format!(
+                        r#"Digest realm="scanner", qop="auth", nonce="n{nonce}", stale={stale}"#
+                    ),
+                )
+            };
+            let Some(authorization) = request.header("Authorization") else {
+                return challenge(false);
+            };
+            if !authorization.contains(&format!(r#"nonce="n{nonce}""#)) {
+                return challenge(true);
+            }
+
+            let nc = authorization
+                .split(", ")
+                .find_map(|param| param.strip_prefix("nc="))
+                .and_then(|nc| u32::from_str_radix(nc, 16).ok())
+                .unwrap_or(0);
+            if nc <= last_nc {
+                return challenge(false);
+            }
+            last_nc = nc;
+            // The nonce expires after two requests
+            if nc == 2 {
+                nonce += 1;
+                last_nc = 0;
+            }
+            TestResponse::ok("status")
+        });
+
+        let credentials = Credentials::new("user", "pass");
+        assert!(get(&server, &credentials).unwrap() == 200);
+        assert!(server.requests().len() == 2);
+        assert!(get(&server, &credentials).unwrap() == 200);
+        assert!(server.requests().len() == 3);
+        // Renewing the stale nonce takes one more request
+        assert!(get(&server, &credentials).unwrap() == 200);
+        assert!(server.requests().len() == 5);
+        assert!(get(&server, &credentials).unwrap() == 200);
+        assert!(server.requests().len() == 6);
+    }
+}
@@ -0,0 +1,187 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{collections::HashMap, fs, path::PathBuf, sync::Mutex};
+
+use crate::{scannererror::ScannerError, structs::ScannerCapabilities};
+
+/// Remembers scanner capabilities by device UUID, so that they need not be
+/// fetched again whenever a scanner is discovered. Entries are kept in
+/// memory and, optionally, as XML files in a directory.
+#[derive(Debug, Default)]
+pub struct CapabilitiesCache {
+    directory: Option<PathBuf>,
+    entries: Mutex<HashMap<String, ScannerCapabilities>>,
+}
+
+impl CapabilitiesCache {
+    pub fn in_memory() -> CapabilitiesCache {
+        CapabilitiesCache::default()
+    }
+
+    /// Also stores the capabilities in `directory`, which is created if
+    /// necessary.
+    pub fn on_disk(directory: impl Into<PathBuf>) -> CapabilitiesCache {
+        CapabilitiesCache {
+            directory: Some(directory.into()),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the capabilities of the scanner with the given UUID. Entries
+    /// for a different eSCL version than the one the scanner announces are
+    /// considered outdated.
+    pub fn get(&self, uuid: &str, version: Option<&str>) -> Option<ScannerCapabilities> {
+        let uuid = normalize_uuid(uuid);
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(&uuid) {
+            let capabilities = self.load(&uuid)?;
+            entries.insert(uuid.clone(), capabilities);
+        }
+
+        let capabilities = entries.get(&uuid)?;
+        if version.is_some_and(|version| version != capabilities.version) {
+            log::info!(
+                "Cached capabilities of {uuid} are for version {}, not {}",
+                capabilities.version,
+                version.unwrap_or_default()
+            );
+            return None;
+        }
+
+        log::debug!("Using cached capabilities of {uuid}");
+        Some(capabilities.clone())
+    }
+
+    /// Parses the capabilities XML as sent by a scanner and stores the result
+    /// under the UUID it contains.
+    pub fn insert(&self, capabilities_xml: &str) -> Result<ScannerCapabilities, ScannerError> {
+        let capabilities: ScannerCapabilities = serde_xml_rs::from_str(capabilities_xml)?;
+        if capabilities.uuid.is_empty() {
+            return Ok(capabilities);
+        }
+
+        let uuid = normalize_uuid(&capabilities.uuid);
+        if let Some(path) = self.cache_file(&uuid) {
+            // The cache still works in memory if the disk is not writable
+            if let Err(err) = path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| fs::write(&path, capabilities_xml))
+            {
+                log::warn!("Failed to store capabilities of {uuid} in {path:?}: {err}");
+            }
+        }
+
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(uuid, capabilities.clone());
+        Ok(capabilities)
+    }
+
+    pub fn remove(&self, uuid: &str) {
+        let uuid = normalize_uuid(uuid);
+        self.entries.lock().unwrap().remove(&uuid);
+        if let Some(path) = self.cache_file(&uuid) {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    fn load(&self, uuid: &str) -> Option<ScannerCapabilities> {
+        let path = self.cache_file(uuid)?;
+        let xml = fs::read_to_string(&path).ok()?;
+        match serde_xml_rs::from_str(&xml) {
+            Ok(capabilities) => Some(capabilities),
+            Err(err) => {
+                log::warn!("Ignoring invalid cached capabilities {path:?}: {err}");
+                None
+            }
+        }
+    }
+
+    // The UUID comes from the network, so anything but hex digits and dashes
+    // could lead outside the directory. Such scanners are cached in memory.
+    fn cache_file(&self, uuid: &str) -> Option<PathBuf> {
+        let directory = self.directory.as_ref()?;
+        if uuid.is_empty() || !uuid.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+            log::warn!("Not caching capabilities of {uuid:?} on disk, invalid UUID");
+            return None;
+        }
+
+        Some(directory.join(format!("{uuid}.xml")))
+    }
+}
+
+// Scanners announce their UUID in different notations via mDNS and in the
+// capabilities
+pub(crate) fn normalize_uuid(uuid: &str) -> String {
+    uuid.trim()
+        .trim_start_matches("urn:uuid:")
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{capscache::CapabilitiesCache, testutil::brother_capabilities_xml};
+
+    const UUID: &str = "e3248000-80ce-11db-8000-3c2af4493199";
+
+    #[test]
+    fn in_memory_cache() {
+        let cache = CapabilitiesCache::in_memory();
+        assert!(cache.get(UUID, None).is_none());
+
+        let capabilities = cache.insert(&brother_capabilities_xml()).unwrap();
+        assert!(capabilities.uuid == UUID);
+        assert!(cache.get(UUID, None).is_some());
+        assert!(cache.get(&UUID.to_uppercase(), Some("2.63")).is_some());
+
+        // A firmware update may change the capabilities
+        assert!(cache.get(UUID, Some("2.64")).is_none());
+
+        cache.remove(UUID);
+        assert!(cache.get(UUID, None).is_none());
+    }
+
+    #[test]
+    fn on_disk_cache() {
+        let directory =
+            std::env::temp_dir().join(format!("escl-scan-caps-test-{}", std::process::id()));
+
+        CapabilitiesCache::on_disk(&directory)
+            .insert(&brother_capabilities_xml())
+            .unwrap();
+        assert!(directory.join(format!("{UUID}.xml")).is_file());
+
+        let cache = CapabilitiesCache::on_disk(&directory);
+        let capabilities = cache
+            .get(UUID, Some("2.63"))
+            .expect("capabilities were stored");
+        assert!(capabilities.make_and_model == "Brother MFC-L2710DW series");
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn invalid_uuid_stays_in_memory() {
+        let directory =
+            std::env::temp_dir().join(format!("escl-scan-caps-uuid-{}", std::process::id()));
+        let uuid = "../../escl-scan-caps-escape";
+        let xml = brother_capabilities_xml().replace(UUID, uuid);
+
+        let cache = CapabilitiesCache::on_disk(directory.join("cache"));
+        assert!(cache.insert(&xml).unwrap().uuid == uuid);
+        assert!(cache.get(uuid, None).is_some());
+        assert!(!directory.join("cache").exists());
+        assert!(!std::env::temp_dir()
+            .join("escl-scan-caps-escape.xml")
+            .exists());
+
+        cache.remove(uuid);
+        assert!(cache.get(uuid, None).is_none());
+    }
+}
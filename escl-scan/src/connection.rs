@@ -0,0 +1,94 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::{auth::Credentials, scannererror::ScannerError};
+
+/// How to connect to a scanner's eSCL service.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConnectionOptions {
+    /// Use HTTPS, as announced by scanners via _uscans._tcp
+    pub secure: bool,
+    /// Defaults to 80 or 443, depending on `secure`
+    pub port: Option<u16>,
+    /// Accept any certificate. Many scanners ship with a self-signed one.
+    pub accept_invalid_certs: bool,
+    /// PEM encoded certificate to trust instead of the system's root
+    /// certificates
+    pub pinned_certificate: Option<Vec<u8>>,
+    /// Sent if the scanner asks for authentication
+    pub credentials: Option<Credentials>,
+}
+
+impl ConnectionOptions {
+    pub fn base_url(&self, ip_or_host: &str, root: &str) -> String {
+        let (scheme, default_port) = match self.secure {
+            true => ("https", 443),
+            false => ("http", 80),
+        };
+
+        // IPv6 addresses need brackets to be told apart from the port
+        let host = match ip_or_host.contains(':') && !ip_or_host.starts_with('[') {
+            true => format!("[{ip_or_host}]"),
+            false => ip_or_host.to_string(),
+        };
+
+        format!(
+            "{}://{}:{}/{}",
+            scheme,
+            host,
+            self.port.unwrap_or(default_port),
+            root.trim_start_matches('/')
+        )
+    }
+
+    pub fn make_client(&self) -> Result<reqwest::blocking::Client, ScannerError> {
+        Ok(self.client_builder()?.build()?)
+    }
+
+    // For clients that need more configuration, e.g. timeouts
+    pub(crate) fn client_builder(&self) -> Result<reqwest::blocking::ClientBuilder, ScannerError> {
+        let mut builder = reqwest::blocking::Client::builder()
+            .danger_accept_invalid_certs(self.accept_invalid_certs);
+        if let Some(certificate) = &self.pinned_certificate {
+            builder = builder
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(reqwest::Certificate::from_pem(certificate)?);
+        }
+
+        Ok(builder)
+    }
+
+    #[cfg(feature = "async")]
+    pub fn make_async_client(&self) -> Result<reqwest::Client, ScannerError> {
+        let mut builder =
+            reqwest::Client::builder().danger_accept_invalid_certs(self.accept_invalid_certs);
+        if let Some(certificate) = &self.pinned_certificate {
+            builder = builder
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(reqwest::Certificate::from_pem(certificate)?);
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::connection::ConnectionOptions;
+
+    #[test]
+    fn base_urls() {
+        let mut options = ConnectionOptions::default();
+        assert!(options.base_url("192.168.1.5", "eSCL") == "http://192.168.1.5:80/eSCL");
+
+        options.secure = true;
+        assert!(options.base_url("scanner.local", "/eSCL") == "https://scanner.local:443/eSCL");
+
+        options.port = Some(8443);
+        assert!(options.base_url("fe80::1", "eSCL") == "https://[fe80::1]:8443/eSCL");
+        assert!(options.base_url("[fe80::1]", "eSCL") == "https://[fe80::1]:8443/eSCL");
+    }
+}
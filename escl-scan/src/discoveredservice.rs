@@ -0,0 +1,261 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{collections::HashMap, net::IpAddr};
+
+use crate::{
+    capscache::normalize_uuid, connection::ConnectionOptions,
+    scanneradvertisement::ScannerAdvertisement,
+};
+
+/// Which addresses to connect to discovered scanners with.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AddressFamily {
+    /// Use the host name and leave the choice to the resolver
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+/// An eSCL service as announced via mDNS, before connecting to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiscoveredService {
+    /// Service instance name, unique on the network
+    pub name: String,
+    /// "uscan" or "uscans"
+    pub service_type: String,
+    pub host_name: String,
+    pub port: u16,
+    pub addresses: Vec<IpAddr>,
+    pub txt: HashMap<String, String>,
+}
+
+impl DiscoveredService {
+    /// Returns a TXT record value. Keys are case insensitive.
+    pub fn txt(&self, key: &str) -> Option<&str> {
+        self.txt
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn advertisement(&self) -> ScannerAdvertisement {
+        ScannerAdvertisement::from_txt(&self.txt)
+    }
+
+    /// Announced via _uscans._tcp, i.e. HTTPS only.
+    pub fn is_secure(&self) -> bool {
+        self.service_type == "uscans"
+    }
+
+    /// Human-readable device name, falls back to the instance name.
+    pub fn device_name(&self) -> &str {
+        self.txt("ty").unwrap_or(&self.name)
+    }
+
+    /// Returns an address of the given family, or the host name if there is
+    /// none.
+    pub fn host(&self, address_family: AddressFamily) -> String {
+        let address = self.addresses.iter().find(|address| match address {
+            IpAddr::V4(_) => address_family == AddressFamily::Ipv4,
+            // Link-local addresses are useless without the interface, which
+            // is not known here
+            IpAddr::V6(address) => {
+                address_family == AddressFamily::Ipv6 && (address.segments()[0] & 0xffc0) != 0xfe80
+            }
+        });
+
+        match address {
+            Some(address) => address.to_string(),
+            None => self.host_name.clone(),
+        }
+    }
+
+    /// URL of the eSCL service, or None if no resource root was announced.
+    pub fn base_url(
+        &self,
+        connection_options: &ConnectionOptions,
+        address_family: AddressFamily,
+    ) -> Option<String> {
+        let connection_options = ConnectionOptions {
+            secure: self.is_secure(),
+            port: Some(self.port),
+            ..connection_options.clone()
+        };
+
+        Some(connection_options.base_url(&self.host(address_family), self.txt("rs")?))
+    }
+
+    /// Identifies the device behind the service, which may be announced on
+    /// several interfaces and via both _uscan._tcp and _uscans._tcp.
+    pub fn device_key(&self) -> String {
+        match self.txt("UUID") {
+            Some(uuid) => normalize_uuid(uuid),
+            None => self.host_name.trim_end_matches('.').to_ascii_lowercase(),
+        }
+    }
+
+    /// Whether the device name, instance name, host name or an address
+    /// contains the given name.
+    pub fn matches(&self, name: &str) -> bool {
+        self.device_name().contains(name)
+            || self.name.contains(name)
+            || self.host_name.contains(name)
+            || self
+                .addresses
+                .iter()
+                .any(|address| address.to_string().contains(name))
+    }
+
+    // Services are reported once per address and interface
+    pub(crate) fn is_same_service(&self, other: &DiscoveredService) -> bool {
+        self.name == other.name && self.service_type == other.service_type
+    }
+
+    pub(crate) fn merge(&mut self, other: DiscoveredService) {
+        self.merge_addresses(other.addresses);
+        self.txt.extend(other.txt);
+    }
+
+    fn merge_addresses(&mut self, addresses: Vec<IpAddr>) {
+        for address in addresses {
+            if !self.addresses.contains(&address) {
+                self.addresses.push(address);
+            }
+        }
+    }
+}
+
+/// Merges services of the same device into one entry with all of their
+/// addresses. Of a device announced both with and without TLS, the secure
+/// service is kept if `prefer_secure` is set.
+pub fn deduplicate(
+    services: Vec<DiscoveredService>,
+    prefer_secure: bool,
+) -> Vec<DiscoveredService> {
+    let mut devices: Vec<DiscoveredService> = vec![];
+    for service in services {
+        let known = devices
+            .iter_mut()
+            .find(|known| known.device_key() == service.device_key());
+        let Some(known) = known else {
+            devices.push(service);
+            continue;
+        };
+
+        if known.is_secure() != service.is_secure() && service.is_secure() == prefer_secure {
+            let addresses = std::mem::take(&mut known.addresses);
+            *known = service;
+            known.merge_addresses(addresses);
+        } else {
+            known.merge_addresses(service.addresses);
+        }
+    }
+
+    devices
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        connection::ConnectionOptions,
+        discoveredservice::{deduplicate, AddressFamily, DiscoveredService},
+    };
+
+    fn brother_service() -> DiscoveredService {
+        DiscoveredService {
+            name: "Brother MFC-L2710DW series".to_string(),
+            service_type: "uscans".to_string(),
+            host_name: "BRW3C2AF4493199.local".to_string(),
+            port: 443,
+            addresses: vec!["192.168.1.5".parse().unwrap()],
+            txt: HashMap::from([
+                ("ty".to_string(), "Brother MFC-L2710DW".to_string()),
+                ("RS".to_string(), "eSCL".to_string()),
+            ]),
+        }
+    }
+
+    #[test]
+    fn service_properties() {
+        let service = brother_service();
+        assert!(service.txt("rs") == Some("eSCL"));
+        assert!(service.device_name() == "Brother MFC-L2710DW");
+        assert!(service.matches("MFC"));
+        assert!(service.matches("BRW3C2AF"));
+        assert!(service.matches("192.168.1."));
+        assert!(!service.matches("HP"));
+        assert!(
+            service.base_url(&ConnectionOptions::default(), AddressFamily::Any)
+                == Some("https://BRW3C2AF4493199.local:443/eSCL".to_string())
+        );
+        assert!(
+            service.base_url(&ConnectionOptions::default(), AddressFamily::Ipv4)
+                == Some("https://192.168.1.5:443/eSCL".to_string())
+        );
+
+        let mut unnamed = brother_service();
+        unnamed.txt.clear();
+        assert!(unnamed.device_name() == "Brother MFC-L2710DW series");
+        assert!(unnamed
+            .base_url(&ConnectionOptions::default(), AddressFamily::Any)
+            .is_none());
+    }
+
+    #[test]
+    fn merge_addresses() {
+        let mut service = brother_service();
+        let mut other = brother_service();
+        other.addresses = vec![
+            "192.168.1.5".parse().unwrap(),
+            "fe80::3e2a:f4ff:fe49:3199".parse().unwrap(),
+        ];
+        assert!(service.is_same_service(&other));
+
+        service.merge(other);
+        assert!(service.addresses.len() == 2);
+    }
+
+    #[test]
+    fn deduplicate_devices() {
+        let mut ipv6 = brother_service();
+        ipv6.addresses = vec![
+            "fe80::3e2a:f4ff:fe49:3199".parse().unwrap(),
+            "2001:db8::3e2a:f4ff:fe49:3199".parse().unwrap(),
+        ];
+        let mut insecure = brother_service();
+        insecure.service_type = "uscan".to_string();
+        insecure.port = 80;
+        let mut other_host = brother_service();
+        other_host.host_name = "NPI1A2B3C.local".to_string();
+        other_host.txt.insert(
+            "UUID".to_string(),
+            "urn:uuid:E3248000-80CE-11DB-8000-3C2AF4493199".to_string(),
+        );
+
+        let services = vec![insecure.clone(), ipv6.clone(), brother_service()];
+        let devices = deduplicate(services.clone(), true);
+        assert!(devices.len() == 1);
+        assert!(devices[0].is_secure());
+        assert!(devices[0].addresses.len() == 3);
+        assert!(devices[0].host(AddressFamily::Ipv6) == "2001:db8::3e2a:f4ff:fe49:3199");
+
+        let devices = deduplicate(services, false);
+        assert!(devices.len() == 1 && !devices[0].is_secure());
+
+        // Different host names, but the same UUID
+        let mut same_uuid = brother_service();
+        same_uuid.txt.insert(
+            "UUID".to_string(),
+            "e3248000-80ce-11db-8000-3c2af4493199".to_string(),
+        );
+        assert!(deduplicate(vec![same_uuid.clone(), other_host.clone()], true).len() == 1);
+        assert!(deduplicate(vec![insecure, other_host], true).len() == 2);
+    }
+}
@@ -0,0 +1,87 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{sync::Arc, time::Duration};
+
+use crate::{discoveredservice::DiscoveredService, scannererror::ScannerError};
+
+/// DNS-SD service types of eSCL scanners, without and with TLS.
+pub const SERVICE_TYPES: [&str; 2] = ["uscan", "uscans"];
+
+/// What a discovery backend reports while browsing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BackendEvent {
+    /// A service was resolved. May be reported again, e.g. once per address.
+    Found(DiscoveredService),
+    /// A service is gone. Not all backends report this.
+    Removed { name: String, service_type: String },
+}
+
+/// An mDNS/DNS-SD implementation to find scanners with. `ScannerFinder`
+/// uses the system's mDNS daemon via zeroconf by default, or a pure Rust
+/// implementation with the mdns-sd feature.
+pub trait DiscoveryBackend: Send + Sync {
+    /// Starts browsing for the given service types, e.g. "uscan". Browsing
+    /// stops when the session is dropped.
+    fn browse(&self, service_types: &[&str]) -> Result<Box<dyn DiscoverySession>, ScannerError>;
+}
+
+pub trait DiscoverySession {
+    /// Waits for at most `timeout` and returns what happened meanwhile.
+    fn poll(&mut self, timeout: Duration) -> Result<Vec<BackendEvent>, ScannerError>;
+
+    /// Whether there is nothing more to report, e.g. because all hosts were
+    /// probed. Browsing via mDNS never ends by itself.
+    fn is_finished(&self) -> bool {
+        false
+    }
+
+    /// Whether services that leave the network are reported as
+    /// `BackendEvent::Removed`. Sessions that don't are restarted when
+    /// watching, see `ScannerFinder::watch()`.
+    fn reports_removals(&self) -> bool {
+        false
+    }
+}
+
+/// The backend selected by cargo features, zeroconf takes precedence.
+/// Without either feature nothing is discovered, unless another backend like
+/// `ProbeBackend` is set on the finder.
+pub fn default_backend() -> Arc<dyn DiscoveryBackend> {
+    #[cfg(feature = "zeroconf")]
+    return Arc::new(crate::zeroconfbackend::ZeroconfBackend);
+
+    #[cfg(all(feature = "mdns-sd", not(feature = "zeroconf")))]
+    return Arc::new(crate::mdnssdbackend::MdnsSdBackend);
+
+    #[cfg(not(any(feature = "zeroconf", feature = "mdns-sd")))]
+    return Arc::new(NoBackend);
+}
+
+#[cfg(not(any(feature = "zeroconf", feature = "mdns-sd")))]
+struct NoBackend;
+
+#[cfg(not(any(feature = "zeroconf", feature = "mdns-sd")))]
+struct NoSession;
+
+#[cfg(not(any(feature = "zeroconf", feature = "mdns-sd")))]
+impl DiscoveryBackend for NoBackend {
+    fn browse(&self, _service_types: &[&str]) -> Result<Box<dyn DiscoverySession>, ScannerError> {
+        log::warn!("Built without an mDNS backend, enable the zeroconf or mdns-sd feature");
+        Ok(Box::new(NoSession))
+    }
+}
+
+#[cfg(not(any(feature = "zeroconf", feature = "mdns-sd")))]
+impl DiscoverySession for NoSession {
+    fn poll(&mut self, _timeout: Duration) -> Result<Vec<BackendEvent>, ScannerError> {
+        Ok(vec![])
+    }
+
+    fn is_finished(&self) -> bool {
+        true
+    }
+}
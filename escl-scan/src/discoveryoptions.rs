@@ -0,0 +1,115 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{sync::Arc, time::Duration};
+
+use crate::discoveredservice::DiscoveredService;
+
+/// Decides whether a discovered service is of interest, e.g. by its name,
+/// UUID or the capabilities it advertises.
+pub type ServiceFilter = Arc<dyn Fn(&DiscoveredService) -> bool + Send + Sync>;
+
+/// When to stop looking for scanners and which ones to report.
+#[derive(Clone)]
+pub struct DiscoveryOptions {
+    /// How long to browse for scanners at most
+    pub timeout: Duration,
+    /// Stop as soon as this many scanners were found
+    pub max_scanners: Option<usize>,
+    /// Stop as soon as a scanner passed the filter
+    pub stop_on_first_match: bool,
+    /// Only report services this returns true for
+    pub filter: Option<ServiceFilter>,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        DiscoveryOptions {
+            timeout: Duration::from_secs(5),
+            max_scanners: None,
+            stop_on_first_match: false,
+            filter: None,
+        }
+    }
+}
+
+impl DiscoveryOptions {
+    pub fn with_filter<F>(filter: F) -> DiscoveryOptions
+    where
+        F: Fn(&DiscoveredService) -> bool + Send + Sync + 'static,
+    {
+        DiscoveryOptions {
+            filter: Some(Arc::new(filter)),
+            ..Default::default()
+        }
+    }
+
+    pub fn matches(&self, service: &DiscoveredService) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter(service))
+    }
+
+    /// Whether discovery can stop with the given number of matching
+    /// scanners.
+    pub fn is_satisfied(&self, found: usize) -> bool {
+        (self.stop_on_first_match && found > 0) || self.max_scanners.is_some_and(|max| found >= max)
+    }
+}
+
+impl std::fmt::Debug for DiscoveryOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DiscoveryOptions")
+            .field("timeout", &self.timeout)
+            .field("max_scanners", &self.max_scanners)
+            .field("stop_on_first_match", &self.stop_on_first_match)
+            .field("filter", &self.filter.as_ref().map(|_| "..."))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{discoveredservice::DiscoveredService, discoveryoptions::DiscoveryOptions};
+
+    #[test]
+    fn stop_conditions() {
+        let options = DiscoveryOptions::default();
+        assert!(!options.is_satisfied(0));
+        assert!(!options.is_satisfied(30));
+
+        let options = DiscoveryOptions {
+            stop_on_first_match: true,
+            ..Default::default()
+        };
+        assert!(!options.is_satisfied(0));
+        assert!(options.is_satisfied(1));
+
+        let options = DiscoveryOptions {
+            max_scanners: Some(2),
+            ..Default::default()
+        };
+        assert!(!options.is_satisfied(1));
+        assert!(options.is_satisfied(2));
+    }
+
+    #[test]
+    fn filter() {
+        let service = DiscoveredService {
+            name: "Brother MFC-L2710DW series".to_string(),
+            service_type: "uscan".to_string(),
+            host_name: "BRW3C2AF4493199.local".to_string(),
+            port: 80,
+            addresses: vec![],
+            txt: HashMap::from([("duplex".to_string(), "F".to_string())]),
+        };
+        assert!(DiscoveryOptions::default().matches(&service));
+
+        let options =
+            DiscoveryOptions::with_filter(|service| service.advertisement().duplex == Some(true));
+        assert!(!options.matches(&service));
+    }
+}
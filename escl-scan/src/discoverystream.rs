@@ -0,0 +1,70 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Receiver,
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{discoveredservice::DiscoveredService, scanner::Scanner, scannererror::ScannerError};
+
+/// Something that happened during discovery, see `ScannerFinder::stream()`.
+#[derive(Debug)]
+pub enum DiscoveryEvent {
+    /// A device was announced for the first time
+    ServiceFound(DiscoveredService),
+    /// A device was connected to, following its ServiceFound event
+    ScannerFound {
+        service: DiscoveredService,
+        scanner: Box<Scanner>,
+    },
+    /// A device is not announced anymore
+    ServiceRemoved(DiscoveredService),
+    /// Discovery failed and stops
+    Error(ScannerError),
+}
+
+/// Yields discovery events as they happen. Discovery runs in the background
+/// until it is done or the stream is dropped.
+pub struct DiscoveryStream {
+    receiver: Receiver<DiscoveryEvent>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl DiscoveryStream {
+    pub(crate) fn new(receiver: Receiver<DiscoveryEvent>, stopped: Arc<AtomicBool>) -> Self {
+        DiscoveryStream { receiver, stopped }
+    }
+
+    /// Waits for the next event for at most `timeout`. Returns None on
+    /// timeout as well as at the end of discovery.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<DiscoveryEvent> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Stops discovery. Events that already happened can still be received.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Iterator for DiscoveryStream {
+    type Item = DiscoveryEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl Drop for DiscoveryStream {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
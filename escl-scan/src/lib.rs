@@ -4,9 +4,35 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-extern crate zeroconf;
-
+#[cfg(feature = "async")]
+pub mod asyncscanjob;
+#[cfg(feature = "async")]
+pub mod asyncscanner;
+pub mod auth;
+pub mod capscache;
+pub mod connection;
+pub mod discoveredservice;
+pub mod discoverybackend;
+pub mod discoveryoptions;
+pub mod discoverystream;
+#[cfg(feature = "mdns-sd")]
+pub mod mdnssdbackend;
+pub mod negotiation;
+pub mod pageorder;
+pub mod pagesink;
+pub mod preflight;
+pub mod probebackend;
+pub mod retry;
+pub mod scanjob;
 pub mod scanner;
+pub mod scanneradvertisement;
+pub mod scannerbuilder;
 pub mod scannererror;
 pub mod scannerfinder;
+pub mod scannermonitor;
 pub mod structs;
+#[cfg(test)]
+mod testutil;
+pub mod validation;
+#[cfg(feature = "zeroconf")]
+pub mod zeroconfbackend;
@@ -0,0 +1,226 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::time::{Duration, Instant};
+
+use mdns_sd::{Receiver, ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::{
+    discoveredservice::DiscoveredService,
+    discoverybackend::{BackendEvent, DiscoveryBackend, DiscoverySession},
+    scannererror::{ErrorCode, ScannerError},
+};
+
+/// Discovers scanners with a pure Rust mDNS implementation, which needs
+/// neither Avahi nor Bonjour.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MdnsSdBackend;
+
+impl DiscoveryBackend for MdnsSdBackend {
+    fn browse(&self, service_types: &[&str]) -> Result<Box<dyn DiscoverySession>, ScannerError> {
+        browse_with(ServiceDaemon::new()?, service_types)
+    }
+}
+
+// Takes the daemon so that tests can restrict it to the loopback interface
+fn browse_with(
+    daemon: ServiceDaemon,
+    service_types: &[&str],
+) -> Result<Box<dyn DiscoverySession>, ScannerError> {
+    let mut receivers = vec![];
+    for service_name in service_types {
+        let service_type = format!("_{service_name}._tcp.local.");
+        log::info!("Looking for scanners with {service_type}");
+        receivers.push(daemon.browse(&service_type)?);
+    }
+
+    Ok(Box::new(MdnsSdSession { daemon, receivers }))
+}
+
+struct MdnsSdSession {
+    daemon: ServiceDaemon,
+    receivers: Vec<Receiver<ServiceEvent>>,
+}
+
+impl DiscoverySession for MdnsSdSession {
+    fn poll(&mut self, timeout: Duration) -> Result<Vec<BackendEvent>, ScannerError> {
+        let end_time = Instant::now() + timeout;
+        let mut events = vec![];
+        loop {
+            for receiver in self.receivers.iter() {
+                events.extend(receiver.try_iter().filter_map(backend_event));
+            }
+
+            let now = Instant::now();
+            if !events.is_empty() || now >= end_time {
+                return Ok(events);
+            }
+            std::thread::sleep((end_time - now).min(Duration::from_millis(10)));
+        }
+    }
+
+    // Also for services whose records expired without a goodbye
+    fn reports_removals(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for MdnsSdSession {
+    fn drop(&mut self) {
+        if let Err(err) = self.daemon.shutdown() {
+            log::warn!("Failed to stop mDNS daemon: {err}");
+        }
+    }
+}
+
+fn backend_event(event: ServiceEvent) -> Option<BackendEvent> {
+    match event {
+        ServiceEvent::ServiceResolved(info) => {
+            log::info!("Service discovered: {info:?}");
+            Some(BackendEvent::Found(DiscoveredService::from(&info)))
+        }
+        ServiceEvent::ServiceRemoved(service_type, fullname) => {
+            log::info!("Service removed: {fullname}");
+            Some(BackendEvent::Removed {
+                name: instance_name(&fullname, &service_type).to_string(),
+                service_type: service_name(&service_type).to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+// "Scanner._uscan._tcp.local." -> "Scanner"
+fn instance_name<'a>(fullname: &'a str, service_type: &str) -> &'a str {
+    fullname
+        .strip_suffix(service_type)
+        .map(|name| name.trim_end_matches('.'))
+        .unwrap_or(fullname)
+}
+
+// "_uscan._tcp.local." -> "uscan"
+fn service_name(service_type: &str) -> &str {
+    service_type
+        .trim_start_matches('_')
+        .split('.')
+        .next()
+        .unwrap_or_default()
+}
+
+impl From<&ServiceInfo> for DiscoveredService {
+    fn from(info: &ServiceInfo) -> Self {
+        DiscoveredService {
+            name: instance_name(info.get_fullname(), info.get_type()).to_string(),
+            service_type: service_name(info.get_type()).to_string(),
+            host_name: info.get_hostname().trim_end_matches('.').to_string(),
+            port: info.get_port(),
+            addresses: info.get_addresses().iter().copied().collect(),
+            txt: info
+                .get_properties()
+                .iter()
+                .map(|property| (property.key().to_string(), property.val_str().to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl From<mdns_sd::Error> for ScannerError {
+    fn from(error: mdns_sd::Error) -> Self {
+        ScannerError {
+            code: ErrorCode::NetworkError,
+            message: error.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::IpAddr, time::Duration};
+
+    use mdns_sd::{IfKind, ServiceDaemon, ServiceInfo};
+
+    use crate::{
+        discoveredservice::DiscoveredService,
+        discoverybackend::BackendEvent,
+        mdnssdbackend::{browse_with, instance_name, service_name},
+    };
+
+    // Keeps the test independent of the network the machine is on
+    fn loopback_daemon() -> ServiceDaemon {
+        let daemon = ServiceDaemon::new().unwrap();
+        daemon.disable_interface(IfKind::All).unwrap();
+        daemon.enable_interface(IfKind::LoopbackV4).unwrap();
+        daemon
+    }
+
+    #[test]
+    fn service_names() {
+        let service_type = "_uscans._tcp.local.";
+        assert!(service_name(service_type) == "uscans");
+        assert!(instance_name("Brother MFC._uscans._tcp.local.", service_type) == "Brother MFC");
+    }
+
+    #[test]
+    fn service_info() {
+        let properties = HashMap::from([
+            ("rs".to_string(), "eSCL".to_string()),
+            ("ty".to_string(), "Brother MFC-L2710DW".to_string()),
+        ]);
+        let info = ServiceInfo::new(
+            "_uscan._tcp.local.",
+            "Brother MFC-L2710DW series",
+            "BRW3C2AF4493199.local.",
+            "192.168.1.5",
+            80,
+            properties,
+        )
+        .unwrap();
+
+        let service = DiscoveredService::from(&info);
+        assert!(service.name == "Brother MFC-L2710DW series");
+        assert!(service.service_type == "uscan");
+        assert!(service.host_name == "BRW3C2AF4493199.local");
+        assert!(service.port == 80);
+        assert!(service.addresses == vec!["192.168.1.5".parse::<IpAddr>().unwrap()]);
+        assert!(service.txt("rs") == Some("eSCL"));
+    }
+
+    #[test]
+    fn in_process_responder() {
+        let responder = loopback_daemon();
+        let info = ServiceInfo::new(
+            "_uscan._tcp.local.",
+            "Test Scanner",
+            "test-scanner.local.",
+            "127.0.0.1",
+            8080,
+            &[("rs", "eSCL"), ("ty", "Test Scanner")][..],
+        )
+        .unwrap();
+        responder.register(info).unwrap();
+
+        let mut session = browse_with(loopback_daemon(), &["uscan"]).unwrap();
+        let mut found = None;
+        for _ in 0..50 {
+            found = session
+                .poll(Duration::from_millis(100))
+                .unwrap()
+                .into_iter()
+                .find_map(|event| match event {
+                    BackendEvent::Found(service) if service.name == "Test Scanner" => Some(service),
+                    _ => None,
+                });
+            if found.is_some() {
+                break;
+            }
+        }
+
+        let service = found.expect("responder was found");
+        assert!(service.port == 8080);
+        assert!(service.device_name() == "Test Scanner");
+        responder.shutdown().unwrap();
+    }
+}
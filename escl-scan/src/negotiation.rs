@@ -0,0 +1,381 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fmt::Display;
+
+use crate::structs::{
+    AdjustmentSupport, ColorMode, DocumentFormat, ImageAdjustment, Range, ScanSettings,
+    ScannerCapabilities, SettingProfile,
+};
+
+/// A change made to the requested scan settings so that the scanner
+/// supports them.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SettingsAdjustment {
+    Resolution {
+        from: (i16, i16),
+        to: (i16, i16),
+    },
+    RegionWidth {
+        from: u16,
+        to: u16,
+    },
+    RegionHeight {
+        from: u16,
+        to: u16,
+    },
+    ColorMode {
+        from: ColorMode,
+        to: ColorMode,
+    },
+    DocumentFormat {
+        from: DocumentFormat,
+        to: DocumentFormat,
+    },
+    /// `to` is None if the scanner does not offer the adjustment at all
+    ImageAdjustment {
+        adjustment: ImageAdjustment,
+        from: i32,
+        to: Option<i32>,
+    },
+}
+
+impl Display for SettingsAdjustment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsAdjustment::Resolution { from, to } => write!(
+                f,
+                "Resolution adjusted from {}x{} to {}x{} DPI",
+                from.0, from.1, to.0, to.1
+            ),
+            SettingsAdjustment::RegionWidth { from, to } => {
+                write!(f, "Scan region width adjusted from {from} to {to}")
+            }
+            SettingsAdjustment::RegionHeight { from, to } => {
+                write!(f, "Scan region height adjusted from {from} to {to}")
+            }
+            SettingsAdjustment::ColorMode { from, to } => {
+                write!(f, "Color mode adjusted from {from} to {to}")
+            }
+            SettingsAdjustment::DocumentFormat { from, to } => {
+                write!(f, "Document format adjusted from {from} to {to}")
+            }
+            SettingsAdjustment::ImageAdjustment {
+                adjustment,
+                from,
+                to: Some(to),
+            } => write!(f, "{adjustment} adjusted from {from} to {to}"),
+            SettingsAdjustment::ImageAdjustment {
+                adjustment,
+                to: None,
+                ..
+            } => write!(
+                f,
+                "{adjustment} is not supported, using the scanner default"
+            ),
+        }
+    }
+}
+
+// Ordered from most to least preferable replacement
+fn color_mode_fallbacks(color_mode: &ColorMode) -> Vec<ColorMode> {
+    use ColorMode::*;
+    match color_mode {
+        BlackAndWhite1 => vec![Grayscale8, Grayscale16, Rgb24, Rgb48],
+        Grayscale8 => vec![Grayscale16, Rgb24, Rgb48, BlackAndWhite1],
+        Grayscale16 => vec![Grayscale8, Rgb48, Rgb24, BlackAndWhite1],
+        Rgb48 => vec![Rgb24, Grayscale16, Grayscale8, BlackAndWhite1],
+        _ => vec![Rgb24, Rgb48, Grayscale8, Grayscale16, BlackAndWhite1],
+    }
+}
+
+const DOCUMENT_FORMAT_FALLBACKS: [DocumentFormat; 4] = [
+    DocumentFormat::Pdf,
+    DocumentFormat::Jpeg,
+    DocumentFormat::Png,
+    DocumentFormat::Tiff,
+];
+
+// Snaps the value to the closest step within the range
+fn closest_in_range(range: &Range, value: u16) -> u16 {
+    let clamped = value.clamp(range.min, range.max);
+    if range.step == 0 {
+        return clamped;
+    }
+
+    let steps = ((clamped - range.min) as f32 / range.step as f32).round() as u16;
+    let snapped = range.min + steps * range.step;
+    if snapped > range.max {
+        snapped - range.step
+    } else {
+        snapped
+    }
+}
+
+fn closest_adjustment(support: &AdjustmentSupport, value: i32) -> i32 {
+    let clamped = value.clamp(support.min, support.max);
+    if support.step <= 0 {
+        return clamped;
+    }
+
+    let steps = ((clamped - support.min) as f32 / support.step as f32).round() as i32;
+    let snapped = support.min + steps * support.step;
+    if snapped > support.max {
+        snapped - support.step
+    } else {
+        snapped
+    }
+}
+
+fn closest_resolution(profiles: &[SettingProfile], x: u16, y: u16) -> Option<(u16, u16)> {
+    let mut candidates = vec![];
+    for resolutions in profiles
+        .iter()
+        .map(|profile| &profile.supported_resolutions)
+    {
+        if let Some(discrete_resolutions) = &resolutions.discrete_resolutions {
+            candidates.extend(
+                discrete_resolutions
+                    .entries
+                    .iter()
+                    .map(|resolution| (resolution.x_resolution, resolution.y_resolution)),
+            );
+        }
+
+        if let Some(range) = &resolutions.resolution_range {
+            candidates.push((
+                closest_in_range(&range.x_resolution_range, x),
+                closest_in_range(&range.y_resolution_range, y),
+            ));
+        }
+    }
+
+    // Prefer the higher resolution if two candidates are equally close
+    let distance = |candidate: &(u16, u16)| x.abs_diff(candidate.0) + y.abs_diff(candidate.1);
+    candidates.into_iter().min_by(|a, b| {
+        distance(a)
+            .cmp(&distance(b))
+            .then((b.0 + b.1).cmp(&(a.0 + a.1)))
+    })
+}
+
+impl ScanSettings {
+    /// Replaces every setting the configured input source does not support
+    /// with the closest supported one. Returns the list of changes made.
+    ///
+    /// An unavailable input source is left untouched, validation will report
+    /// it.
+    pub fn negotiate(&mut self, capabilities: &ScannerCapabilities) -> Vec<SettingsAdjustment> {
+        let input_caps =
+            match capabilities.input_caps(&self.input_source, self.duplex.unwrap_or(false)) {
+                Some(input_caps) => input_caps,
+                None => return vec![],
+            };
+
+        let mut adjustments = vec![];
+        let profiles = &input_caps.setting_profiles.entries;
+
+        let x = u16::try_from(self.x_resolution).unwrap_or(0);
+        let y = u16::try_from(self.y_resolution).unwrap_or(0);
+        if !profiles
+            .iter()
+            .any(|profile| profile.supports_resolution(x, y))
+        {
+            if let Some((new_x, new_y)) = closest_resolution(profiles, x, y) {
+                let from = (self.x_resolution, self.y_resolution);
+                self.x_resolution = new_x as i16;
+                self.y_resolution = new_y as i16;
+                adjustments.push(SettingsAdjustment::Resolution {
+                    from,
+                    to: (self.x_resolution, self.y_resolution),
+                });
+            }
+        }
+
+        let region = &mut self.scan_regions;
+        let max_width = input_caps
+            .max_width
+            .saturating_sub(region.x_offset.max(0) as u16);
+        let width = region.width.min(max_width).max(input_caps.min_width);
+        if width != region.width {
+            adjustments.push(SettingsAdjustment::RegionWidth {
+                from: region.width,
+                to: width,
+            });
+            region.width = width;
+        }
+        let max_height = input_caps
+            .max_height
+            .saturating_sub(region.y_offset.max(0) as u16);
+        let height = region.height.min(max_height).max(input_caps.min_height);
+        if height != region.height {
+            adjustments.push(SettingsAdjustment::RegionHeight {
+                from: region.height,
+                to: height,
+            });
+            region.height = height;
+        }
+
+        if !profiles
+            .iter()
+            .any(|profile| profile.supports_color_mode(&self.color_mode))
+        {
+            let replacement = color_mode_fallbacks(&self.color_mode)
+                .into_iter()
+                .find(|mode| {
+                    profiles
+                        .iter()
+                        .any(|profile| profile.supports_color_mode(mode))
+                })
+                .or_else(|| {
+                    profiles
+                        .iter()
+                        .flat_map(|profile| profile.color_modes.entries.iter())
+                        .next()
+                        .cloned()
+                });
+
+            if let Some(replacement) = replacement {
+                adjustments.push(SettingsAdjustment::ColorMode {
+                    from: self.color_mode.clone(),
+                    to: replacement.clone(),
+                });
+                self.color_mode = replacement;
+            }
+        }
+
+        if !profiles
+            .iter()
+            .any(|profile| profile.supports_document_format(&self.document_format))
+        {
+            let replacement = DOCUMENT_FORMAT_FALLBACKS
+                .into_iter()
+                .find(|format| {
+                    profiles
+                        .iter()
+                        .any(|profile| profile.supports_document_format(format))
+                })
+                .or_else(|| {
+                    profiles
+                        .iter()
+                        .flat_map(|profile| profile.document_formats.supported_formats().iter())
+                        .next()
+                        .cloned()
+                });
+
+            if let Some(replacement) = replacement {
+                adjustments.push(SettingsAdjustment::DocumentFormat {
+                    from: self.document_format.clone(),
+                    to: replacement.clone(),
+                });
+                self.document_format = replacement;
+            }
+        }
+
+        for adjustment in ImageAdjustment::ALL {
+            let value = match self.adjustment(adjustment) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let replacement = capabilities
+                .adjustment_support(adjustment)
+                .map(|support| closest_adjustment(support, value));
+            if replacement != Some(value) {
+                *self.adjustment_mut(adjustment) = replacement;
+                adjustments.push(SettingsAdjustment::ImageAdjustment {
+                    adjustment,
+                    from: value,
+                    to: replacement,
+                });
+            }
+        }
+
+        adjustments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::negotiation::{closest_adjustment, closest_in_range, SettingsAdjustment};
+    use crate::structs::*;
+    use crate::testutil::{brother_capabilities, scan_settings};
+
+    #[test]
+    fn supported_settings_are_kept() {
+        let caps = brother_capabilities();
+        let mut scan_settings = scan_settings(InputSource::Platen);
+        assert!(scan_settings.negotiate(&caps).is_empty());
+    }
+
+    #[test]
+    fn unsupported_settings_are_adjusted() {
+        let caps = brother_capabilities();
+        let mut scan_settings = scan_settings(InputSource::Platen);
+        scan_settings.x_resolution = 250;
+        scan_settings.y_resolution = 250;
+        scan_settings.scan_regions.height = 4000;
+        scan_settings.color_mode = ColorMode::Grayscale16;
+        scan_settings.document_format = DocumentFormat::Tiff;
+        scan_settings.brightness = Some(10);
+
+        let adjustments = scan_settings.negotiate(&caps);
+        assert!(
+            adjustments
+                == vec![
+                    SettingsAdjustment::Resolution {
+                        from: (250, 250),
+                        to: (300, 300)
+                    },
+                    SettingsAdjustment::RegionHeight {
+                        from: 4000,
+                        to: 3507
+                    },
+                    SettingsAdjustment::ColorMode {
+                        from: ColorMode::Grayscale16,
+                        to: ColorMode::Grayscale8
+                    },
+                    SettingsAdjustment::DocumentFormat {
+                        from: DocumentFormat::Tiff,
+                        to: DocumentFormat::Pdf
+                    },
+                    SettingsAdjustment::ImageAdjustment {
+                        adjustment: ImageAdjustment::Brightness,
+                        from: 10,
+                        to: None
+                    },
+                ]
+        );
+        assert!(scan_settings.validate(&caps).is_empty());
+    }
+
+    #[test]
+    fn resolution_range_snapping() {
+        let range = Range {
+            min: 75,
+            max: 1200,
+            normal: 300,
+            step: 50,
+        };
+        assert!(closest_in_range(&range, 50) == 75);
+        assert!(closest_in_range(&range, 250) == 275);
+        assert!(closest_in_range(&range, 1190) == 1175);
+        assert!(closest_in_range(&range, 2400) == 1175);
+    }
+
+    #[test]
+    fn adjustment_snapping() {
+        let support = AdjustmentSupport {
+            min: -100,
+            max: 100,
+            normal: 0,
+            step: 25,
+        };
+        assert!(closest_adjustment(&support, -120) == -100);
+        assert!(closest_adjustment(&support, 30) == 25);
+        assert!(closest_adjustment(&support, 90) == 100);
+    }
+}
@@ -0,0 +1,82 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+/// The order in which a scanner delivers the pages of a duplex scan.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PageOrder {
+    /// Pages arrive in reading order, or the order does not matter.
+    #[default]
+    AsDelivered,
+    /// All front sides arrive first, followed by all back sides.
+    FrontsThenBacks,
+    /// All front sides arrive first, followed by all back sides starting
+    /// with the last sheet.
+    FrontsThenReversedBacks,
+}
+
+impl PageOrder {
+    /// Brings the pages of a complete job into reading order.
+    pub fn arrange<T>(&self, mut pages: Vec<T>) -> Vec<T> {
+        match self {
+            PageOrder::AsDelivered => pages,
+            PageOrder::FrontsThenBacks | PageOrder::FrontsThenReversedBacks => {
+                // An odd page count means the last back side is missing,
+                // e.g. because the scanner dropped a blank page.
+                let backs = pages.split_off(pages.len().div_ceil(2));
+                interleave_pages(pages, backs, *self == PageOrder::FrontsThenReversedBacks)
+            }
+        }
+    }
+}
+
+/// Merges separately scanned front and back sides into reading order.
+/// Surplus pages of either side are appended at the end.
+pub fn interleave_pages<T>(fronts: Vec<T>, mut backs: Vec<T>, backs_reversed: bool) -> Vec<T> {
+    if backs_reversed {
+        backs.reverse();
+    }
+
+    let mut pages = Vec::with_capacity(fronts.len() + backs.len());
+    let mut fronts = fronts.into_iter();
+    let mut backs = backs.into_iter();
+    loop {
+        match (fronts.next(), backs.next()) {
+            (None, None) => return pages,
+            (front, back) => {
+                pages.extend(front);
+                pages.extend(back);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pageorder::{interleave_pages, PageOrder};
+
+    #[test]
+    fn arrange_pages() {
+        let delivered = vec!["1", "3", "5", "2", "4", "6"];
+        assert!(PageOrder::AsDelivered.arrange(delivered.clone()) == delivered);
+        assert!(
+            PageOrder::FrontsThenBacks.arrange(delivered) == vec!["1", "2", "3", "4", "5", "6"]
+        );
+        assert!(
+            PageOrder::FrontsThenReversedBacks.arrange(vec!["1", "3", "5", "6", "4", "2"])
+                == vec!["1", "2", "3", "4", "5", "6"]
+        );
+        assert!(
+            PageOrder::FrontsThenReversedBacks.arrange(vec!["1", "3", "5", "4", "2"])
+                == vec!["1", "2", "3", "4", "5"]
+        );
+    }
+
+    #[test]
+    fn interleave_manual_duplex() {
+        assert!(interleave_pages(vec![1, 3, 5], vec![6, 4, 2], true) == vec![1, 2, 3, 4, 5, 6]);
+        assert!(interleave_pages(vec![1, 3], vec![2], false) == vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,532 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::{
+    scannererror::{ErrorCode, ScannerError},
+    structs::{DocumentFormat, ScanSettings},
+};
+use lopdf::{Bookmark, Document, Object, ObjectId};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Describes a scanned page handed to a `PageSink`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageInfo {
+    /// Position of the page within the scan, starting at 1.
+    pub index: usize,
+    pub document_format: DocumentFormat,
+    pub x_resolution: i16,
+    pub y_resolution: i16,
+}
+
+impl PageInfo {
+    pub fn new(scan_settings: &ScanSettings, index: usize) -> PageInfo {
+        PageInfo {
+            index,
+            document_format: scan_settings.document_format.clone(),
+            x_resolution: scan_settings.x_resolution,
+            y_resolution: scan_settings.y_resolution,
+        }
+    }
+
+    pub fn mime_type(&self) -> &str {
+        self.document_format.as_str()
+    }
+
+    pub fn file_extension(&self) -> &str {
+        self.document_format.file_extension()
+    }
+}
+
+/// Receives the pages of a scan in reading order.
+pub trait PageSink {
+    fn store_page(&mut self, info: &PageInfo, page: Vec<u8>) -> Result<(), ScannerError>;
+
+    /// Called once after the last page was stored.
+    fn finish(&mut self) -> Result<(), ScannerError> {
+        Ok(())
+    }
+}
+
+/// Keeps all pages in memory.
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    pub pages: Vec<(PageInfo, Vec<u8>)>,
+}
+
+impl PageSink for MemorySink {
+    fn store_page(&mut self, info: &PageInfo, page: Vec<u8>) -> Result<(), ScannerError> {
+        self.pages.push((info.clone(), page));
+        Ok(())
+    }
+}
+
+/// Writes every page to its own file in a directory, named like
+/// `<prefix>_<index>.<extension>`.
+#[derive(Debug)]
+pub struct DirectorySink {
+    directory: PathBuf,
+    prefix: String,
+}
+
+impl DirectorySink {
+    pub fn new(directory: impl Into<PathBuf>, prefix: &str) -> DirectorySink {
+        DirectorySink {
+            directory: directory.into(),
+            prefix: prefix.to_string(),
+        }
+    }
+}
+
+impl PageSink for DirectorySink {
+    fn store_page(&mut self, info: &PageInfo, page: Vec<u8>) -> Result<(), ScannerError> {
+        fs::create_dir_all(&self.directory)?;
+        let page_path = self.directory.join(format!(
+            "{}_{}.{}",
+            self.prefix,
+            info.index,
+            info.file_extension()
+        ));
+        log::info!("Storing scanned page as {page_path:?}");
+        fs::write(page_path, page)?;
+        Ok(())
+    }
+}
+
+/// Merges all pages into a single PDF document, which is written when the
+/// scan is finished. Pages are appended if the document exists already.
+#[derive(Debug)]
+pub struct MergedPdfSink {
+    path: PathBuf,
+    pages: Vec<Vec<u8>>,
+}
+
+impl MergedPdfSink {
+    pub fn new(path: impl Into<PathBuf>) -> MergedPdfSink {
+        MergedPdfSink {
+            path: path.into(),
+            pages: vec![],
+        }
+    }
+}
+
+impl PageSink for MergedPdfSink {
+    fn store_page(&mut self, info: &PageInfo, page: Vec<u8>) -> Result<(), ScannerError> {
+        if info.document_format != DocumentFormat::Pdf {
+            return Err(ScannerError {
+                code: ErrorCode::PdfError,
+                message: format!("Cannot merge {} pages into a PDF", info.mime_type()),
+            });
+        }
+
+        self.pages.push(page);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ScannerError> {
+        let pages = std::mem::take(&mut self.pages);
+        if pages.is_empty() {
+            return Ok(());
+        }
+
+        if !self.path.exists() && pages.len() == 1 {
+            log::info!("Storing scanned page as {:?}", self.path);
+            fs::write(&self.path, &pages[0])?;
+            return Ok(());
+        }
+
+        let mut documents = vec![];
+        if self.path.exists() {
+            log::info!("Appending pages to existing document {:?}", self.path);
+            documents.push(Document::load(&self.path)?);
+        }
+        for page in pages {
+            documents.push(Document::load_mem(&page)?);
+        }
+
+        merge_documents(documents, &self.path)
+    }
+}
+
+/// Stores pages the way the command line tool always did: PDF pages are
+/// merged into the destination file, other formats are written to numbered
+/// files next to it if it exists already. The file extension is changed if
+/// it does not fit the format of the pages.
+#[derive(Debug)]
+pub struct FileSink {
+    destination_file: String,
+    pdf: MergedPdfSink,
+}
+
+impl FileSink {
+    pub fn new(destination_file: &str) -> FileSink {
+        FileSink {
+            destination_file: destination_file.to_string(),
+            pdf: MergedPdfSink::new(DocumentFormat::Pdf.file_name(destination_file)),
+        }
+    }
+
+    fn make_page_file_name(
+        &self,
+        new_page_idx: usize,
+        document_format: &DocumentFormat,
+    ) -> Result<String, ScannerError> {
+        let destination_file = document_format.file_name(&self.destination_file);
+        let destination_file = destination_file.as_str();
+        if !Path::new(destination_file).exists() {
+            log::info!("Destination file does not exist yet");
+            return Ok(destination_file.into());
+        }
+
+        // We cannot write to destination_file, try numbering pages
+        if let Some(last_dot_pos) = destination_file.rfind('.') {
+            let (path_part, ext_part) = destination_file.split_at(last_dot_pos);
+
+            let page_file_name = format!("{path_part}_{new_page_idx}{ext_part}");
+            if !Path::new(&page_file_name).exists() {
+                log::info!("Created page file name \"{page_file_name}\"");
+                return Ok(page_file_name);
+            }
+
+            let numbered_path_part = format!("{path_part}_{new_page_idx}");
+            for i in 1..u16::MAX {
+                let fallback_numbered_file_name = format!("{numbered_path_part}_{i}{ext_part}");
+                if !Path::new(&fallback_numbered_file_name).exists() {
+                    log::info!("Created fallback file name \"{fallback_numbered_file_name}\"");
+                    return Ok(fallback_numbered_file_name);
+                }
+            }
+
+            return Err(ScannerError {
+                code: ErrorCode::FilesystemError,
+                message: format!(
+                    "Failed to determine an alternative to existing destination file: \"{}\"",
+                    destination_file
+                ),
+            });
+        }
+
+        Err(ScannerError {
+            code: ErrorCode::NoFileExtension,
+            message: destination_file.to_string(),
+        })
+    }
+}
+
+impl PageSink for FileSink {
+    fn store_page(&mut self, info: &PageInfo, page: Vec<u8>) -> Result<(), ScannerError> {
+        if info.document_format == DocumentFormat::Pdf {
+            return self.pdf.store_page(info, page);
+        }
+
+        let page_file_name = self.make_page_file_name(info.index, &info.document_format)?;
+        log::info!("Storing scanned page as {page_file_name}");
+        fs::write(&page_file_name, page)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), ScannerError> {
+        self.pdf.finish()
+    }
+}
+
+// Modelled after the lopdf example: https://crates.io/crates/lopdf
+fn merge_error(message: &str) -> ScannerError {
+    log::error!("Failed to merge PDF documents: {message}");
+    ScannerError {
+        code: ErrorCode::PdfError,
+        message: message.to_string(),
+    }
+}
+
+fn merge_documents(documents: Vec<Document>, output_path: &Path) -> Result<(), ScannerError> {
+    // Define a starting max_id (will be used as start index for object_ids)
+    let mut max_id = 1;
+    let mut pagenum = 1;
+    // Collect all Documents Objects grouped by a map
+    let mut documents_pages = BTreeMap::new();
+    let mut documents_objects = BTreeMap::new();
+    let mut document = Document::with_version("1.5");
+
+    for mut doc in documents {
+        let mut first = false;
+        doc.renumber_objects_with(max_id);
+
+        max_id = doc.max_id + 1;
+
+        documents_pages.extend(
+            doc.get_pages()
+                .into_values()
+                .map(|object_id| {
+                    if !first {
+                        let bookmark = Bookmark::new(
+                            format!("Page_{}", pagenum),
+                            [0.0, 0.0, 1.0],
+                            0,
+                            object_id,
+                        );
+                        document.add_bookmark(bookmark, None);
+                        first = true;
+                        pagenum += 1;
+                    }
+
+                    (object_id, doc.get_object(object_id).unwrap().to_owned())
+                })
+                .collect::<BTreeMap<ObjectId, Object>>(),
+        );
+        documents_objects.extend(doc.objects);
+    }
+
+    // Catalog and Pages are mandatory
+    let mut catalog_object: Option<(ObjectId, Object)> = None;
+    let mut pages_object: Option<(ObjectId, Object)> = None;
+
+    // Process all objects except "Page" type
+    for (object_id, object) in documents_objects.iter() {
+        // We have to ignore "Page" (as are processed later), "Outlines" and "Outline" objects
+        // All other objects should be collected and inserted into the main Document
+        match object.type_name().unwrap_or("") {
+            "Catalog" => {
+                // Collect a first "Catalog" object and use it for the future "Pages"
+                catalog_object = Some((
+                    if let Some((id, _)) = catalog_object {
+                        id
+                    } else {
+                        *object_id
+                    },
+                    object.clone(),
+                ));
+            }
+            "Pages" => {
+                // Collect and update a first "Pages" object and use it for the future "Catalog"
+                // We have also to merge all dictionaries of the old and the new "Pages" object
+                if let Ok(dictionary) = object.as_dict() {
+                    let mut dictionary = dictionary.clone();
+                    if let Some((_, ref object)) = pages_object {
+                        if let Ok(old_dictionary) = object.as_dict() {
+                            dictionary.extend(old_dictionary);
+                        }
+                    }
+
+                    pages_object = Some((
+                        if let Some((id, _)) = pages_object {
+                            id
+                        } else {
+                            *object_id
+                        },
+                        Object::Dictionary(dictionary),
+                    ));
+                }
+            }
+            "Page" => {}     // Ignored, processed later and separately
+            "Outlines" => {} // Ignored, not supported yet
+            "Outline" => {}  // Ignored, not supported yet
+            _ => {
+                document.objects.insert(*object_id, object.clone());
+            }
+        }
+    }
+
+    // Without them, there is nothing to hold the pages
+    let Some(pages_object) = pages_object else {
+        return Err(merge_error("Pages root not found"));
+    };
+    let Some(catalog_object) = catalog_object else {
+        return Err(merge_error("Catalog root not found"));
+    };
+
+    // Iterate over all "Page" objects and collect into the parent "Pages" created before
+    for (object_id, object) in documents_pages.iter() {
+        if let Ok(dictionary) = object.as_dict() {
+            let mut dictionary = dictionary.clone();
+            dictionary.set("Parent", pages_object.0);
+            document
+                .objects
+                .insert(*object_id, Object::Dictionary(dictionary));
+        }
+    }
+
+    // Build a new "Pages" with updated fields
+    if let Ok(dictionary) = pages_object.1.as_dict() {
+        let mut dictionary = dictionary.clone();
+
+        // Set new pages count
+        dictionary.set("Count", documents_pages.len() as u32);
+
+        // Set new "Kids" list (collected from documents pages) for "Pages"
+        dictionary.set(
+            "Kids",
+            documents_pages
+                .into_keys()
+                .map(Object::Reference)
+                .collect::<Vec<_>>(),
+        );
+
+        document
+            .objects
+            .insert(pages_object.0, Object::Dictionary(dictionary));
+    }
+
+    // Build a new "Catalog" with updated fields
+    if let Ok(dictionary) = catalog_object.1.as_dict() {
+        let mut dictionary = dictionary.clone();
+        dictionary.set("Pages", pages_object.0);
+        dictionary.remove(b"Outlines"); // Outlines not supported in merged PDFs
+        document
+            .objects
+            .insert(catalog_object.0, Object::Dictionary(dictionary));
+    }
+
+    document.trailer.set("Root", catalog_object.0);
+
+    // Update the max internal ID as wasn't updated before due to direct objects insertion
+    document.max_id = document.objects.len() as u32;
+
+    // Reorder all new Document objects
+    document.renumber_objects();
+
+    //Set any Bookmarks to the First child if they are not set to a page
+    document.adjust_zero_pages();
+
+    //Set all bookmarks to the PDF Object tree then set the Outlines to the Bookmark content map.
+    if let Some(n) = document.build_outline() {
+        if let Ok(Object::Dictionary(ref mut dict)) = document.get_object_mut(catalog_object.0) {
+            dict.set("Outlines", Object::Reference(n));
+        }
+    }
+
+    document.compress();
+    if let Err(err) = document.save(output_path) {
+        log::error!("Failed to save merged pdf document: {err:?}");
+        return Err(err.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use lopdf::{dictionary, Document, Object};
+
+    use crate::pagesink::{
+        merge_documents, DirectorySink, FileSink, MemorySink, MergedPdfSink, PageInfo, PageSink,
+    };
+    use crate::scannererror::ErrorCode;
+    use crate::structs::DocumentFormat;
+
+    fn jpeg_page(index: usize) -> PageInfo {
+        PageInfo {
+            index,
+            document_format: DocumentFormat::Jpeg,
+            x_resolution: 300,
+            y_resolution: 300,
+        }
+    }
+
+    fn pdf_page(index: usize) -> PageInfo {
+        PageInfo {
+            document_format: DocumentFormat::Pdf,
+            ..jpeg_page(index)
+        }
+    }
+
+    // A single empty A4 page, like a scanner delivers it
+    fn pdf_document() -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+        });
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+
+        let mut pdf = vec![];
+        document.save_to(&mut pdf).unwrap();
+        pdf
+    }
+
+    #[test]
+    fn memory_sink() {
+        let mut sink = MemorySink::default();
+        assert!(sink.store_page(&jpeg_page(1), vec![1]).is_ok());
+        assert!(sink.store_page(&jpeg_page(2), vec![2]).is_ok());
+        assert!(sink.finish().is_ok());
+        assert!(sink.pages == vec![(jpeg_page(1), vec![1]), (jpeg_page(2), vec![2])]);
+        assert!(sink.pages[0].0.mime_type() == "image/jpeg");
+    }
+
+    #[test]
+    fn file_sinks() {
+        let directory = std::env::temp_dir().join(format!("escl-scan-test-{}", std::process::id()));
+
+        let mut sink = DirectorySink::new(&directory, "page");
+        assert!(sink.store_page(&jpeg_page(1), vec![1]).is_ok());
+        assert!(std::fs::read(directory.join("page_1.jpg")).unwrap() == vec![1]);
+
+        // Existing files are not overwritten, pages are numbered instead
+        let destination_file = directory.join("page_1.jpg");
+        let mut sink = FileSink::new(destination_file.to_str().unwrap());
+        assert!(sink.store_page(&jpeg_page(2), vec![2]).is_ok());
+        assert!(std::fs::read(directory.join("page_1_2.jpg")).unwrap() == vec![2]);
+        assert!(std::fs::read(&destination_file).unwrap() == vec![1]);
+
+        // The format may differ from the requested one after negotiation
+        let mut sink = FileSink::new(directory.join("scan.pdf").to_str().unwrap());
+        assert!(sink.store_page(&jpeg_page(1), vec![3]).is_ok());
+        assert!(std::fs::read(directory.join("scan.jpg")).unwrap() == vec![3]);
+        assert!(!directory.join("scan.pdf").exists());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn merged_pdf_sink() {
+        let directory =
+            std::env::temp_dir().join(format!("escl-scan-pdf-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("scan.pdf");
+
+        let mut sink = MergedPdfSink::new(&path);
+        assert!(sink.store_page(&pdf_page(1), pdf_document()).is_ok());
+        assert!(sink.store_page(&pdf_page(2), pdf_document()).is_ok());
+        assert!(sink.finish().is_ok());
+        assert!(Document::load(&path).unwrap().get_pages().len() == 2);
+
+        // Later scans are appended
+        let mut sink = MergedPdfSink::new(&path);
+        assert!(sink.store_page(&pdf_page(1), pdf_document()).is_ok());
+        assert!(sink.finish().is_ok());
+        assert!(Document::load(&path).unwrap().get_pages().len() == 3);
+
+        assert!(sink.store_page(&jpeg_page(1), vec![1]).is_err());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn merge_without_pages() {
+        let path = std::env::temp_dir().join(format!("escl-scan-empty-{}.pdf", std::process::id()));
+        let err = merge_documents(vec![Document::with_version("1.5")], &path).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::PdfError));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,144 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::{
+    scannererror::{ErrorCode, ScannerError},
+    structs::{AdfState, InputSource, ScannerState, ScannerStatus},
+};
+
+impl ScannerStatus {
+    /// Checks whether the scanner can start a job from the given input
+    /// source right now.
+    pub fn check_ready(&self, input_source: &InputSource) -> Result<(), ScannerError> {
+        if self.state != ScannerState::Idle {
+            return Err(ScannerError {
+                code: ErrorCode::ScannerNotReady,
+                message: format!("Scanner state is {}", self.state),
+            });
+        }
+
+        if *input_source != InputSource::Feeder {
+            return Ok(());
+        }
+
+        // Not all scanners report the feeder state, let the job decide then
+        let adf_state = match &self.adf_state {
+            Some(adf_state) => adf_state,
+            None => return Ok(()),
+        };
+
+        let code = match adf_state {
+            AdfState::ScannerAdfLoaded => return Ok(()),
+            // Still feeding the previous job, this passes by itself
+            AdfState::ScannerAdfProcessing => ErrorCode::ScannerNotReady,
+            AdfState::ScannerAdfEmpty => ErrorCode::FeederEmpty,
+            AdfState::ScannerAdfJam
+            | AdfState::ScannerAdfMispick
+            | AdfState::ScannerAdfMultipickDetected => ErrorCode::FeederJam,
+            AdfState::ScannerAdfDoorOpen | AdfState::ScannerAdfHatchOpen => ErrorCode::FeederOpen,
+            AdfState::Other(value) => {
+                log::warn!("Unknown feeder state {value}, trying to scan anyway");
+                return Ok(());
+            }
+            AdfState::ScannerAdfDuplexPageTooShort
+            | AdfState::ScannerAdfDuplexPageTooLong
+            | AdfState::ScannerAdfInputTrayFailed
+            | AdfState::ScannerAdfInputTrayOverloaded => ErrorCode::FeederNotReady,
+        };
+
+        Err(ScannerError {
+            code,
+            message: adf_state.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::retry::RetryPolicy;
+    use crate::scanner::Scanner;
+    use crate::scannererror::ErrorCode;
+    use crate::structs::*;
+
+    fn status(state: ScannerState, adf_state: Option<AdfState>) -> ScannerStatus {
+        ScannerStatus {
+            state,
+            adf_state,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ready_states() {
+        let idle = status(ScannerState::Idle, Some(AdfState::ScannerAdfEmpty));
+        assert!(idle.check_ready(&InputSource::Platen).is_ok());
+
+        let loaded = status(ScannerState::Idle, Some(AdfState::ScannerAdfLoaded));
+        assert!(loaded.check_ready(&InputSource::Feeder).is_ok());
+
+        let unknown = status(ScannerState::Idle, None);
+        assert!(unknown.check_ready(&InputSource::Feeder).is_ok());
+    }
+
+    #[test]
+    fn not_ready_states() {
+        for state in [
+            ScannerState::Processing,
+            ScannerState::Testing,
+            ScannerState::Stopped,
+            ScannerState::Down,
+        ] {
+            let result = status(state, None).check_ready(&InputSource::Platen);
+            assert!(matches!(
+                result,
+                Err(err) if matches!(err.code, ErrorCode::ScannerNotReady)
+            ));
+        }
+
+        let feeder_error = |adf_state| {
+            status(ScannerState::Idle, Some(adf_state))
+                .check_ready(&InputSource::Feeder)
+                .unwrap_err()
+                .code
+        };
+        assert!(matches!(
+            feeder_error(AdfState::ScannerAdfEmpty),
+            ErrorCode::FeederEmpty
+        ));
+        assert!(matches!(
+            feeder_error(AdfState::ScannerAdfJam),
+            ErrorCode::FeederJam
+        ));
+        assert!(matches!(
+            feeder_error(AdfState::ScannerAdfHatchOpen),
+            ErrorCode::FeederOpen
+        ));
+        assert!(matches!(
+            feeder_error(AdfState::ScannerAdfInputTrayFailed),
+            ErrorCode::FeederNotReady
+        ));
+    }
+
+    #[test]
+    fn busy_feeder_is_waited_for() {
+        let busy = status(ScannerState::Idle, Some(AdfState::ScannerAdfProcessing));
+        let err = busy.check_ready(&InputSource::Feeder).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::ScannerNotReady));
+
+        let retry_policy = RetryPolicy::default();
+        let delay =
+            Scanner::preflight_delay(&retry_policy, &busy, &InputSource::Feeder, Instant::now());
+        assert!(matches!(delay, Ok(Some(_))));
+
+        // A jammed feeder fails right away
+        let jammed = status(ScannerState::Idle, Some(AdfState::ScannerAdfJam));
+        let delay =
+            Scanner::preflight_delay(&retry_policy, &jammed, &InputSource::Feeder, Instant::now());
+        assert!(delay.is_err());
+    }
+}
@@ -0,0 +1,481 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    auth,
+    capscache::CapabilitiesCache,
+    connection::ConnectionOptions,
+    discoveredservice::DiscoveredService,
+    discoverybackend::{BackendEvent, DiscoveryBackend, DiscoverySession},
+    scannererror::{ErrorCode, ScannerError},
+    structs::ScannerCapabilities,
+};
+
+// Most of the time is spent waiting for addresses nobody uses
+const PROBE_WORKERS: usize = 128;
+
+// A /22 for IPv4, a /118 for IPv6. With the default options this takes
+// well under a minute to probe, larger ranges could outlast any sensible
+// discovery timeout and would be cut short.
+const MAX_RANGE_BITS: u32 = 10;
+
+/// Where to look for eSCL services on each probed host.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeOptions {
+    /// 443 and 8443 are probed with HTTPS, all others with HTTP
+    pub ports: Vec<u16>,
+    /// Tried in order on every open port
+    pub resource_roots: Vec<String>,
+    /// For connecting as well as for fetching the capabilities
+    pub timeout: Duration,
+}
+
+impl Default for ProbeOptions {
+    fn default() -> Self {
+        ProbeOptions {
+            ports: vec![80, 443, 8080, 8443],
+            resource_roots: vec!["eSCL".to_string(), "escl".to_string()],
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Finds scanners by asking every given host for its eSCL capabilities, for
+/// networks that mDNS does not reach, e.g. other subnets. Use it with
+/// `ScannerFinder::backend()`. The discovery timeout should leave enough time
+/// to probe all hosts.
+#[derive(Clone, Debug)]
+pub struct ProbeBackend {
+    targets: Vec<String>,
+    options: ProbeOptions,
+    connection_options: ConnectionOptions,
+    capabilities_cache: Option<Arc<CapabilitiesCache>>,
+}
+
+impl ProbeBackend {
+    /// Probes the given IP addresses or host names, see `parse_targets()`.
+    pub fn new(targets: Vec<String>) -> ProbeBackend {
+        ProbeBackend {
+            targets,
+            options: ProbeOptions::default(),
+            connection_options: ConnectionOptions::default(),
+            capabilities_cache: None,
+        }
+    }
+
+    pub fn options(mut self, options: ProbeOptions) -> ProbeBackend {
+        self.options = options;
+        self
+    }
+
+    /// Certificate options and credentials. Scheme and port depend on the
+    /// probed port.
+    pub fn connection_options(mut self, connection_options: ConnectionOptions) -> ProbeBackend {
+        self.connection_options = connection_options;
+        self
+    }
+
+    /// Stores the capabilities of the scanners found, so that a finder
+    /// using the same cache need not fetch them again.
+    pub fn capabilities_cache(mut self, cache: Arc<CapabilitiesCache>) -> ProbeBackend {
+        self.capabilities_cache = Some(cache);
+        self
+    }
+}
+
+impl DiscoveryBackend for ProbeBackend {
+    fn browse(&self, service_types: &[&str]) -> Result<Box<dyn DiscoverySession>, ScannerError> {
+        let client = self
+            .connection_options
+            .client_builder()?
+            .timeout(self.options.timeout)
+            .build()?;
+        let (sender, receiver) = mpsc::channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let prober = Prober {
+            backend: self.clone(),
+            service_types: service_types.iter().map(|ty| ty.to_string()).collect(),
+            client,
+            stopped: Arc::clone(&stopped),
+        };
+        std::thread::spawn(move || prober.run(&sender));
+
+        Ok(Box::new(ProbeSession {
+            receiver,
+            stopped,
+            finished: false,
+        }))
+    }
+}
+
+struct ProbeSession {
+    receiver: Receiver<DiscoveredService>,
+    stopped: Arc<AtomicBool>,
+    finished: bool,
+}
+
+impl DiscoverySession for ProbeSession {
+    fn poll(&mut self, timeout: Duration) -> Result<Vec<BackendEvent>, ScannerError> {
+        let mut events = vec![];
+        match self.receiver.recv_timeout(timeout) {
+            Ok(service) => events.push(BackendEvent::Found(service)),
+            Err(RecvTimeoutError::Timeout) => {}
+            // All hosts were probed
+            Err(RecvTimeoutError::Disconnected) => self.finished = true,
+        }
+
+        events.extend(self.receiver.try_iter().map(BackendEvent::Found));
+        Ok(events)
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl Drop for ProbeSession {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+struct Prober {
+    backend: ProbeBackend,
+    service_types: Vec<String>,
+    client: reqwest::blocking::Client,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Prober {
+    fn run(&self, sender: &Sender<DiscoveredService>) {
+        let endpoints: Vec<(&str, u16)> = self
+            .backend
+            .targets
+            .iter()
+            .flat_map(|host| {
+                self.backend
+                    .options
+                    .ports
+                    .iter()
+                    .map(move |port| (host.as_str(), *port))
+            })
+            .collect();
+        log::info!("Probing {} endpoints for eSCL", endpoints.len());
+
+        let next = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..PROBE_WORKERS.min(endpoints.len()) {
+                scope.spawn(|| loop {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((host, port)) = endpoints.get(idx) else {
+                        break;
+                    };
+                    if self.stopped.load(Ordering::Relaxed) {
+                        break;
+                    }
+
+                    if let Some(service) = self.probe(host, *port) {
+                        log::info!("Service probed: {service:?}");
+                        if sender.send(service).is_err() {
+                            self.stopped.store(true, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    fn probe(&self, host: &str, port: u16) -> Option<DiscoveredService> {
+        let secure = matches!(port, 443 | 8443);
+        let service_type = if secure { "uscans" } else { "uscan" };
+        if !self.service_types.iter().any(|ty| ty == service_type) {
+            return None;
+        }
+
+        // Don't wait for an HTTP timeout per resource root if nobody listens
+        let address = (host, port).to_socket_addrs().ok()?.next()?;
+        TcpStream::connect_timeout(&address, self.backend.options.timeout).ok()?;
+
+        let connection_options = ConnectionOptions {
+            secure,
+            port: Some(port),
+            ..self.backend.connection_options.clone()
+        };
+        for root in self.backend.options.resource_roots.iter() {
+            let base_url = connection_options.base_url(host, root);
+            match self.get_capabilities(&base_url, &connection_options) {
+                Ok(capabilities) => {
+                    return Some(make_service(
+                        host,
+                        address.ip(),
+                        port,
+                        service_type,
+                        root,
+                        capabilities,
+                    ));
+                }
+                Err(err) => log::debug!("No eSCL service at {base_url}: {err}"),
+            }
+        }
+
+        None
+    }
+
+    fn get_capabilities(
+        &self,
+        base_url: &str,
+        connection_options: &ConnectionOptions,
+    ) -> Result<ScannerCapabilities, ScannerError> {
+        let response = auth::send(connection_options.credentials.as_ref(), || {
+            self.client.get(format!("{base_url}/ScannerCapabilities"))
+        })?;
+        if !response.status().is_success() {
+            return Err(ScannerError {
+                code: ErrorCode::ProtocolError,
+                message: format!("HTTP status {}", response.status()),
+            });
+        }
+
+        let capabilities_xml = response.text()?;
+        let capabilities: ScannerCapabilities = serde_xml_rs::from_str(&capabilities_xml)?;
+        // Parsing is lenient enough to accept about any XML document
+        if capabilities.version.is_empty() {
+            return Err(ScannerError {
+                code: ErrorCode::ProtocolError,
+                message: "Response is not eSCL scanner capabilities".to_string(),
+            });
+        }
+
+        if let Some(cache) = &self.backend.capabilities_cache {
+            cache.insert(&capabilities_xml)?;
+        }
+
+        Ok(capabilities)
+    }
+}
+
+// Describes a probed scanner like it would announce itself via mDNS
+fn make_service(
+    host: &str,
+    address: IpAddr,
+    port: u16,
+    service_type: &str,
+    resource_root: &str,
+    capabilities: ScannerCapabilities,
+) -> DiscoveredService {
+    let device_name = match capabilities.make_and_model.is_empty() {
+        true => host.to_string(),
+        false => capabilities.make_and_model,
+    };
+    let mut txt = HashMap::from([
+        ("rs".to_string(), resource_root.to_string()),
+        ("ty".to_string(), device_name.clone()),
+        ("vers".to_string(), capabilities.version),
+    ]);
+    if !capabilities.uuid.is_empty() {
+        txt.insert("UUID".to_string(), capabilities.uuid);
+    }
+
+    DiscoveredService {
+        // Instance names are unique in mDNS, model names are not
+        name: format!("{device_name} ({host})"),
+        service_type: service_type.to_string(),
+        host_name: host.to_string(),
+        port,
+        addresses: vec![address],
+        txt,
+    }
+}
+
+/// Parses a list of IP addresses, host names and CIDR ranges separated by
+/// commas or whitespace, e.g. "10.0.3.0/24, scanner.example.com". Ranges
+/// may contain up to 1024 addresses.
+pub fn parse_targets(targets: &str) -> Result<Vec<String>, ScannerError> {
+    let mut hosts = vec![];
+    for target in targets
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|target| !target.is_empty())
+    {
+        match target.contains('/') {
+            true => hosts.extend(expand_range(target)?),
+            false => hosts.push(target.to_string()),
+        }
+    }
+
+    if hosts.is_empty() {
+        return Err(ScannerError {
+            code: ErrorCode::InvalidAddress,
+            message: format!("No hosts to probe in \"{targets}\""),
+        });
+    }
+
+    Ok(hosts)
+}
+
+fn expand_range(range: &str) -> Result<Vec<String>, ScannerError> {
+    let invalid = |reason: &str| ScannerError {
+        code: ErrorCode::InvalidAddress,
+        message: format!("{range}: {reason}"),
+    };
+
+    let (address, prefix) = range.split_once('/').expect("range contains a slash");
+    let address: IpAddr = address.parse().map_err(|_| invalid("Not an IP address"))?;
+    let prefix: u32 = prefix.parse().map_err(|_| invalid("Invalid prefix"))?;
+    let bits = match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    if prefix > bits {
+        return Err(invalid("Invalid prefix"));
+    }
+    if bits - prefix > MAX_RANGE_BITS {
+        return Err(invalid(&format!(
+            "Ranges larger than /{} are not supported",
+            bits - MAX_RANGE_BITS
+        )));
+    }
+
+    let count = 1u128 << (bits - prefix);
+    let first = match address {
+        IpAddr::V4(address) => u32::from(address) as u128,
+        IpAddr::V6(address) => u128::from(address),
+    } & !(count - 1);
+
+    // Network and broadcast addresses of IPv4 subnets are no hosts
+    let offsets = match address {
+        IpAddr::V4(_) if count > 2 => 1..count - 1,
+        _ => 0..count,
+    };
+    Ok(offsets
+        .map(|offset| match address {
+            IpAddr::V4(_) => Ipv4Addr::from((first + offset) as u32).to_string(),
+            IpAddr::V6(_) => Ipv6Addr::from(first + offset).to_string(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{TcpListener, TcpStream},
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        capscache::CapabilitiesCache,
+        discoverybackend::{BackendEvent, DiscoveryBackend, SERVICE_TYPES},
+        discoveryoptions::DiscoveryOptions,
+        probebackend::{parse_targets, ProbeBackend, ProbeOptions},
+        scannerfinder::ScannerFinder,
+        testutil::{brother_capabilities_xml, TestResponse, TestServer},
+    };
+
+    #[test]
+    fn targets() {
+        let hosts = parse_targets("10.0.3.0/30, scanner.example.com,192.168.1.5").unwrap();
+        assert!(hosts == vec!["10.0.3.1", "10.0.3.2", "scanner.example.com", "192.168.1.5"]);
+
+        assert!(parse_targets("10.0.3.7/24").unwrap().len() == 254);
+        assert!(parse_targets("10.0.3.7/32").unwrap() == vec!["10.0.3.7"]);
+        assert!(parse_targets("fd00::/127").unwrap() == vec!["fd00::", "fd00::1"]);
+
+        assert!(parse_targets("10.0.0.0/22").unwrap().len() == 1022);
+        assert!(parse_targets("10.0.0.0/21").is_err());
+        assert!(parse_targets("fd00::/117").is_err());
+        assert!(parse_targets("10.0.3.0/33").is_err());
+        assert!(parse_targets("scanner/24").is_err());
+        assert!(parse_targets(" , ").is_err());
+    }
+
+    #[test]
+    fn probe_local_server() {
+        // Answers like a scanner with a lowercase resource root
+        let capabilities = brother_capabilities_xml();
+        let server = TestServer::start(move |request| match request.path.as_str() {
+            "/escl/ScannerCapabilities" => TestResponse::ok(capabilities.clone()),
+            _ => TestResponse::status(404),
+        });
+        let port: u16 = server.url.rsplit(':').next().unwrap().parse().unwrap();
+        let fetched = || {
+            server
+                .requests()
+                .iter()
+                .filter(|request| *request == "GET /escl/ScannerCapabilities")
+                .count()
+        };
+
+        // Nobody listens on the port of another closed listener
+        let closed_port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        assert!(TcpStream::connect(("127.0.0.1", closed_port)).is_err());
+
+        let backend = ProbeBackend::new(vec!["127.0.0.1".to_string()]).options(ProbeOptions {
+            ports: vec![closed_port, port],
+            ..Default::default()
+        });
+        let mut session = backend.browse(&SERVICE_TYPES).unwrap();
+        let mut services = vec![];
+        for _ in 0..50 {
+            services.extend(
+                session
+                    .poll(Duration::from_millis(100))
+                    .unwrap()
+                    .into_iter()
+                    .filter_map(|event| match event {
+                        BackendEvent::Found(service) => Some(service),
+                        _ => None,
+                    }),
+            );
+            if !services.is_empty() {
+                break;
+            }
+        }
+
+        assert!(services.len() == 1);
+        let service = &services[0];
+        assert!(service.port == port);
+        assert!(service.service_type == "uscan");
+        assert!(service.device_name() == "Brother MFC-L2710DW series");
+        assert!(service.txt("rs") == Some("escl"));
+        assert!(service.device_key() == "e3248000-80ce-11db-8000-3c2af4493199");
+        assert!(fetched() == 1);
+
+        // The finder takes the capabilities from the probe and stops once all
+        // hosts were probed
+        let cache = Arc::new(CapabilitiesCache::in_memory());
+        let start = Instant::now();
+        let scanners = ScannerFinder::new()
+            .backend(Arc::new(backend.capabilities_cache(Arc::clone(&cache))))
+            .discovery_options(DiscoveryOptions {
+                timeout: Duration::from_secs(30),
+                ..Default::default()
+            })
+            .capabilities_cache(cache)
+            .find(None)
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(scanners.len() == 1);
+        assert!(scanners[0].base_url == format!("http://127.0.0.1:{port}/escl"));
+        assert!(scanners[0].capabilities().unwrap().make_and_model == "Brother MFC-L2710DW series");
+        assert!(fetched() == 2);
+    }
+}
@@ -0,0 +1,232 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::time::{Duration, Instant};
+
+use reqwest::{header::HeaderMap, StatusCode};
+
+use crate::{auth, auth::Credentials, scannererror::ScannerError};
+
+/// How long to wait for a busy scanner. Scanners answer 503 while warming up
+/// or while a page is still being scanned, and report a state other than
+/// Idle while a previous job is finishing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Overall time to wait for the scanner before giving up
+    pub max_wait: Duration,
+    /// Delay before the first retry if the scanner sends no Retry-After
+    pub initial_delay: Duration,
+    /// Upper bound for delays, including the ones asked for by the scanner
+    pub max_delay: Duration,
+    /// Factor the delay grows by with every retry
+    pub backoff_factor: u32,
+    /// Interval for polling the scanner status while waiting for it to idle
+    pub poll_interval: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_wait: Duration::from_secs(60),
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            backoff_factor: 2,
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fails on the first busy response.
+    pub fn no_retry() -> RetryPolicy {
+        RetryPolicy {
+            max_wait: Duration::ZERO,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the delay before the given retry, starting at 0, or None if it
+    /// would exceed the maximum wait time.
+    pub fn delay(
+        &self,
+        retry: u32,
+        retry_after: Option<Duration>,
+        elapsed: Duration,
+    ) -> Option<Duration> {
+        let delay = retry_after
+            .unwrap_or_else(|| {
+                self.initial_delay
+                    .saturating_mul(self.backoff_factor.saturating_pow(retry))
+            })
+            .min(self.max_delay);
+
+        if elapsed + delay > self.max_wait {
+            return None;
+        }
+
+        Some(delay)
+    }
+
+    /// Returns the time to wait before polling the scanner status again, or
+    /// None if it would exceed the maximum wait time.
+    pub fn poll_delay(&self, elapsed: Duration) -> Option<Duration> {
+        if elapsed + self.poll_interval > self.max_wait {
+            return None;
+        }
+
+        Some(self.poll_interval)
+    }
+
+    // Returns how long to wait before repeating a request that got the given
+    // response, or None if the response is final. Shared by send() and
+    // send_async(), which only do the I/O.
+    fn busy_delay(
+        &self,
+        retry: u32,
+        status: StatusCode,
+        headers: &HeaderMap,
+        start: Instant,
+    ) -> Option<Duration> {
+        if status != StatusCode::SERVICE_UNAVAILABLE {
+            return None;
+        }
+
+        let delay = self.delay(retry, retry_after(headers), start.elapsed())?;
+        log::info!("Scanner is busy, retrying in {delay:?}");
+        Some(delay)
+    }
+
+    /// Sends the request built by `make_request` until the scanner stops
+    /// answering 503 or the maximum wait time is up. The last response is
+    /// returned either way.
+    pub(crate) fn send<F>(
+        &self,
+        credentials: Option<&Credentials>,
+        make_request: F,
+    ) -> Result<reqwest::blocking::Response, ScannerError>
+    where
+        F: Fn() -> reqwest::blocking::RequestBuilder,
+    {
+        let start = Instant::now();
+        let mut retry = 0;
+        loop {
+            let response = auth::send(credentials, &make_request)?;
+            match self.busy_delay(retry, response.status(), response.headers(), start) {
+                Some(delay) => std::thread::sleep(delay),
+                None => return Ok(response),
+            }
+            retry += 1;
+        }
+    }
+
+    /// See `send()`.
+    #[cfg(feature = "async")]
+    pub(crate) async fn send_async<F>(
+        &self,
+        credentials: Option<&Credentials>,
+        make_request: F,
+    ) -> Result<reqwest::Response, ScannerError>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        let start = Instant::now();
+        let mut retry = 0;
+        loop {
+            let response = auth::send_async(credentials, &make_request).await?;
+            match self.busy_delay(retry, response.status(), response.headers(), start) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Ok(response),
+            }
+            retry += 1;
+        }
+    }
+}
+
+// Only the delay-seconds form is supported, scanners don't send dates
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use reqwest::{
+        header::{HeaderMap, HeaderValue, RETRY_AFTER},
+        StatusCode,
+    };
+
+    use crate::retry::{retry_after, RetryPolicy};
+
+    #[test]
+    fn retry_delays() {
+        let policy = RetryPolicy::default();
+        let start = Duration::ZERO;
+        assert!(policy.delay(0, None, start) == Some(Duration::from_millis(500)));
+        assert!(policy.delay(2, None, start) == Some(Duration::from_secs(2)));
+        assert!(policy.delay(10, None, start) == Some(Duration::from_secs(10)));
+        assert!(
+            policy.delay(0, Some(Duration::from_secs(3)), start) == Some(Duration::from_secs(3))
+        );
+        assert!(
+            policy.delay(0, Some(Duration::from_secs(300)), start) == Some(Duration::from_secs(10))
+        );
+        assert!(policy.delay(0, None, Duration::from_secs(60)).is_none());
+        assert!(RetryPolicy::no_retry().delay(0, None, start).is_none());
+
+        assert!(policy.poll_delay(start) == Some(Duration::from_secs(1)));
+        assert!(policy.poll_delay(Duration::from_secs(59)) == Some(Duration::from_secs(1)));
+        assert!(policy.poll_delay(Duration::from_millis(59500)).is_none());
+    }
+
+    #[test]
+    fn busy_responses() {
+        let policy = RetryPolicy::default();
+        let mut headers = HeaderMap::new();
+        let start = Instant::now();
+        assert!(policy
+            .busy_delay(0, StatusCode::OK, &headers, start)
+            .is_none());
+        assert!(policy
+            .busy_delay(0, StatusCode::NOT_FOUND, &headers, start)
+            .is_none());
+        assert!(
+            policy.busy_delay(0, StatusCode::SERVICE_UNAVAILABLE, &headers, start)
+                == Some(Duration::from_millis(500))
+        );
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3"));
+        assert!(
+            policy.busy_delay(0, StatusCode::SERVICE_UNAVAILABLE, &headers, start)
+                == Some(Duration::from_secs(3))
+        );
+        assert!(RetryPolicy::no_retry()
+            .busy_delay(0, StatusCode::SERVICE_UNAVAILABLE, &headers, start)
+            .is_none());
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let mut headers = HeaderMap::new();
+        assert!(retry_after(&headers).is_none());
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("5"));
+        assert!(retry_after(&headers) == Some(Duration::from_secs(5)));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert!(retry_after(&headers).is_none());
+    }
+}
@@ -0,0 +1,244 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::Write;
+
+use crate::{
+    auth::{self, Credentials},
+    retry::RetryPolicy,
+    scanner::Scanner,
+    scannererror::{ErrorCode, ScannerError},
+    structs,
+};
+
+/// Takes the job's UUID from the last segment of its URL.
+pub(crate) fn job_uuid(job_url: &str) -> Option<&str> {
+    job_url
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .filter(|uuid| !uuid.is_empty())
+}
+
+/// A scan job that was accepted by the scanner. Pages are fetched one at a
+/// time with `next_page()` until it returns None.
+#[derive(Debug)]
+pub struct ScanJob {
+    client: reqwest::blocking::Client,
+    job_url: String,
+    retry_policy: RetryPolicy,
+    credentials: Option<Credentials>,
+    pages_downloaded: usize,
+    finished: bool,
+}
+
+impl ScanJob {
+    pub(crate) fn new(
+        client: reqwest::blocking::Client,
+        job_url: String,
+        retry_policy: RetryPolicy,
+        credentials: Option<Credentials>,
+    ) -> ScanJob {
+        ScanJob {
+            client,
+            job_url,
+            retry_policy,
+            credentials,
+            pages_downloaded: 0,
+            finished: false,
+        }
+    }
+
+    /// The absolute URL of this job, as announced by the scanner.
+    pub fn url(&self) -> &str {
+        &self.job_url
+    }
+
+    /// The job's UUID, taken from the last segment of its URL.
+    pub fn uuid(&self) -> Option<&str> {
+        job_uuid(&self.job_url)
+    }
+
+    pub fn pages_downloaded(&self) -> usize {
+        self.pages_downloaded
+    }
+
+    /// Whether the scanner reported that there are no more pages.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Downloads the next page. Returns None once the scanner has no more
+    /// pages for this job.
+    pub fn next_page(&mut self) -> Result<Option<Vec<u8>>, ScannerError> {
+        let mut page = vec![];
+        Ok(self.next_page_to(&mut page)?.map(|_| page))
+    }
+
+    /// Like `next_page()`, but streams the page into `writer` instead of
+    /// keeping it in memory. Returns the size of the page.
+    pub fn next_page_to(&mut self, writer: &mut impl Write) -> Result<Option<u64>, ScannerError> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        // Scanners answer 503 while the page is still being scanned
+        let mut response = self.retry_policy.send(self.credentials.as_ref(), || {
+            self.client.get(format!("{}/NextDocument", self.job_url))
+        })?;
+        if response.status() == 404 {
+            log::info!("There is no page {}, we're done", self.pages_downloaded + 1);
+            self.finished = true;
+            return Ok(None);
+        }
+
+        if response.status().is_client_error() || response.status().is_server_error() {
+            return Err(Scanner::make_status_error(
+                response.status(),
+                &response.text()?,
+            ));
+        }
+
+        let size = response.copy_to(writer)?;
+        self.pages_downloaded += 1;
+        log::info!("Downloaded page {} ({size} bytes)", self.pages_downloaded);
+        Ok(Some(size))
+    }
+
+    /// Queries details about the most recently scanned page.
+    pub fn image_info(&self) -> Result<structs::ScanImageInfo, ScannerError> {
+        let response = auth::send(self.credentials.as_ref(), || {
+            self.client.get(format!("{}/ScanImageInfo", self.job_url))
+        })?;
+        if response.status().is_client_error() || response.status().is_server_error() {
+            return Err(Scanner::make_status_error(
+                response.status(),
+                &response.text()?,
+            ));
+        }
+
+        let response_string = response.text()?;
+        log::debug!("> ScanImageInfo: {response_string}");
+        Ok(serde_xml_rs::from_str(&response_string)?)
+    }
+
+    /// Cancels the job after `err` stopped it, so the scanner doesn't wait
+    /// for its job timeout. Returns `err`.
+    pub(crate) fn abort(self, err: ScannerError) -> ScannerError {
+        if let Err(cancel_err) = self.cancel() {
+            log::warn!("Failed to cancel scan job: {cancel_err}");
+        }
+        err
+    }
+
+    /// Deletes the job on the scanner, which stops a running scan and frees
+    /// the scanner without waiting for its job timeout.
+    pub fn cancel(self) -> Result<(), ScannerError> {
+        log::info!("Cancelling scan job {}", self.job_url);
+        let response = auth::send(self.credentials.as_ref(), || {
+            self.client.delete(&self.job_url)
+        })?;
+        if response.status().is_client_error() || response.status().is_server_error() {
+            return Err(ScannerError {
+                code: ErrorCode::NetworkError,
+                message: format!(
+                    "Failed to cancel job {}: Status Code: {:?}, Text: {}",
+                    self.job_url,
+                    response.status(),
+                    response.text()?
+                ),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::retry::RetryPolicy;
+    use crate::scanjob::ScanJob;
+    use crate::scanner::Scanner;
+    use crate::structs::InputSource;
+    use crate::testutil::{fake_scanner, scan_settings};
+
+    #[test]
+    fn job_uuid() {
+        let client = reqwest::blocking::Client::new();
+        let job = ScanJob::new(
+            client.clone(),
+            "http://scanner/eSCL/ScanJobs/71af3a90-8d5b-11ee-80cb-3c2af4493199".to_string(),
+            RetryPolicy::default(),
+            None,
+        );
+        assert!(job.uuid() == Some("71af3a90-8d5b-11ee-80cb-3c2af4493199"));
+        assert!(job.pages_downloaded() == 0);
+        assert!(!job.is_finished());
+
+        let job = ScanJob::new(
+            client,
+            "http://scanner/eSCL/ScanJobs/1234/".to_string(),
+            RetryPolicy::default(),
+            None,
+        );
+        assert!(job.uuid() == Some("1234"));
+    }
+
+    #[test]
+    fn pages_and_image_info() {
+        let server = fake_scanner(vec![vec![b"page 1".to_vec(), b"page 2".to_vec()]]);
+        let scanner = Scanner::from_url(&format!("{}/eSCL", server.url)).unwrap();
+        let mut job = scanner
+            .create_scan_job(&scan_settings(InputSource::Feeder))
+            .unwrap();
+        assert!(job.url() == format!("{}/eSCL/ScanJobs/1", server.url));
+
+        let mut page = vec![];
+        assert!(job.next_page_to(&mut page).unwrap() == Some(6));
+        assert!(page == b"page 1");
+        let info = job.image_info().unwrap();
+        assert!(info.actual_width == Some(2480));
+        assert!(info.actual_height == Some(3507));
+
+        assert!(job.next_page().unwrap() == Some(b"page 2".to_vec()));
+        assert!(job.next_page().unwrap().is_none());
+        assert!(job.is_finished());
+        assert!(job.pages_downloaded() == 2);
+        // The scanner is not asked again once it said there are no more pages
+        assert!(job.next_page().unwrap().is_none());
+        assert!(
+            server
+                .requests()
+                .iter()
+                .filter(|request| request.ends_with("/NextDocument"))
+                .count()
+                == 3
+        );
+    }
+
+    #[test]
+    fn cancel_job() {
+        let server = fake_scanner(vec![vec![b"page 1".to_vec(), b"page 2".to_vec()]]);
+        let scanner = Scanner::from_url(&format!("{}/eSCL", server.url)).unwrap();
+        let mut job = scanner
+            .create_scan_job(&scan_settings(InputSource::Feeder))
+            .unwrap();
+        assert!(job.next_page().unwrap().is_some());
+        job.cancel().unwrap();
+        assert!(server
+            .requests()
+            .contains(&"DELETE /eSCL/ScanJobs/1".to_string()));
+
+        // Unknown jobs can't be cancelled
+        let job = ScanJob::new(
+            reqwest::blocking::Client::new(),
+            format!("{}/eSCL/ScanJobs/2", server.url),
+            RetryPolicy::default(),
+            None,
+        );
+        assert!(job.cancel().is_err());
+    }
+}
@@ -7,35 +7,138 @@
 extern crate reqwest;
 extern crate serde;
 extern crate serde_xml_rs;
-extern crate uuid;
 
 use crate::{
+    auth::{self, Credentials},
+    capscache::CapabilitiesCache,
+    connection::ConnectionOptions,
+    negotiation::SettingsAdjustment,
+    pageorder::{interleave_pages, PageOrder},
+    pagesink::{FileSink, PageInfo, PageSink},
+    retry::RetryPolicy,
+    scanjob::ScanJob,
+    scanneradvertisement::ScannerAdvertisement,
+    scannerbuilder::ScannerBuilder,
     scannererror::{ErrorCode, ScannerError},
     structs::{self},
 };
-use lopdf::{Bookmark, Document, Object, ObjectId};
-use std::{collections::BTreeMap, fmt::Display, fs, path::Path};
-use uuid::Uuid;
+use std::{
+    fmt::Display,
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
 
 #[derive(Clone, Debug)]
 pub struct Scanner {
     pub base_url: String,
     pub device_name: String,
-    pub capabilities: structs::ScannerCapabilities,
+    pub retry_policy: RetryPolicy,
+    /// What the scanner announced via mDNS, if it was discovered
+    pub advertisement: Option<ScannerAdvertisement>,
+    pub(crate) connection_options: ConnectionOptions,
+    pub(crate) client: reqwest::blocking::Client,
+    // Loaded on first use in lazy mode, see `capabilities()`
+    pub(crate) capabilities: OnceLock<structs::ScannerCapabilities>,
+    pub(crate) capabilities_cache: Option<Arc<CapabilitiesCache>>,
+    // As announced via mDNS, used to look up cached capabilities
+    pub(crate) uuid: Option<String>,
+    pub(crate) version: Option<String>,
 }
 
 impl Scanner {
-    fn make_base_url(ip_or_host: &str, root: &str) -> String {
-        format!("http://{}:80/{}", ip_or_host, root)
+    /// Validates the settings and serializes them for the ScanJobs request.
+    pub(crate) fn make_scan_request_body(
+        scan_settings: &structs::ScanSettings,
+        capabilities: &structs::ScannerCapabilities,
+    ) -> Result<String, ScannerError> {
+        let violations = scan_settings.validate(capabilities);
+        if !violations.is_empty() {
+            return Err(ScannerError {
+                code: ErrorCode::InvalidSettings,
+                message: violations
+                    .iter()
+                    .map(|violation| violation.to_string())
+                    .collect::<Vec<String>>()
+                    .join("; "),
+            });
+        }
+
+        let request_body = serde_xml_rs::to_string(scan_settings)?;
+        Ok(format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>{}",
+            request_body
+        ))
+    }
+
+    /// Returns the absolute URL of a newly created scan job. Some scanners
+    /// send a path only.
+    pub(crate) fn make_job_url(
+        base_url: &str,
+        headers: &reqwest::header::HeaderMap,
+    ) -> Result<String, ScannerError> {
+        let location = match headers.get("location") {
+            Some(location) => location.to_str().map_err(|err| ScannerError {
+                code: ErrorCode::ProtocolError,
+                message: format!("Invalid 'location' header: {err}"),
+            })?,
+            None => {
+                return Err(ScannerError {
+                    code: ErrorCode::ProtocolError,
+                    message: format!("Failed to get 'location' header from response: {headers:#?}"),
+                });
+            }
+        };
+
+        match reqwest::Url::parse(base_url).and_then(|base_url| base_url.join(location)) {
+            Ok(job_url) => Ok(job_url.to_string()),
+            Err(err) => Err(ScannerError {
+                code: ErrorCode::ProtocolError,
+                message: format!("Invalid job location {location}: {err}"),
+            }),
+        }
+    }
+
+    pub(crate) fn make_status_error(status: reqwest::StatusCode, text: &str) -> ScannerError {
+        ScannerError {
+            code: ErrorCode::NetworkError,
+            message: format!("Status Code: {:?}, Text: {}", status, text),
+        }
     }
 
-    fn get_capabilities(base_url: &str) -> Result<structs::ScannerCapabilities, ScannerError> {
-        let response = reqwest::blocking::get(&format!("{}/ScannerCapabilities", base_url))?;
-        let response_string = response.text().expect("text is a string");
+    fn credentials(&self) -> Option<&Credentials> {
+        self.connection_options.credentials.as_ref()
+    }
+
+    fn get_capabilities(&self) -> Result<structs::ScannerCapabilities, ScannerError> {
+        if let (Some(cache), Some(uuid)) = (&self.capabilities_cache, &self.uuid) {
+            if let Some(capabilities) = cache.get(uuid, self.version.as_deref()) {
+                return Ok(capabilities);
+            }
+        }
+
+        log::info!("Getting capabilities of {}", self.device_name);
+        let response = auth::send(self.credentials(), || {
+            self.client
+                .get(format!("{}/ScannerCapabilities", self.base_url))
+        })?;
+        let response_string = response.text()?;
         log::debug!("> Capabilities: {response_string}");
-        let scanner_capabilities: structs::ScannerCapabilities =
-            serde_xml_rs::from_str(&response_string)?;
-        Ok(scanner_capabilities)
+
+        match &self.capabilities_cache {
+            Some(cache) => cache.insert(&response_string),
+            None => Ok(serde_xml_rs::from_str(&response_string)?),
+        }
+    }
+
+    /// Returns the capabilities of the scanner, fetching them first if the
+    /// scanner was built in lazy mode.
+    pub fn capabilities(&self) -> Result<&structs::ScannerCapabilities, ScannerError> {
+        if let Some(capabilities) = self.capabilities.get() {
+            return Ok(capabilities);
+        }
+
+        let capabilities = self.get_capabilities()?;
+        Ok(self.capabilities.get_or_init(|| capabilities))
     }
 
     pub fn new(
@@ -43,425 +146,461 @@ impl Scanner {
         ip_or_host: &str,
         resource_root: &str,
     ) -> Result<Scanner, ScannerError> {
-        let base_url = Scanner::make_base_url(ip_or_host, resource_root);
-        let capabilities = Self::get_capabilities(&base_url)?;
-
-        Ok(Scanner {
-            device_name: device_name.to_string(),
-            base_url,
-            capabilities,
-        })
+        Self::with_options(
+            device_name,
+            ip_or_host,
+            resource_root,
+            ConnectionOptions::default(),
+        )
+    }
+
+    /// Connects to the scanner with HTTPS, a different port or certificate
+    /// options.
+    pub fn with_options(
+        device_name: &str,
+        ip_or_host: &str,
+        resource_root: &str,
+        connection_options: ConnectionOptions,
+    ) -> Result<Scanner, ScannerError> {
+        ScannerBuilder::new(&connection_options.base_url(ip_or_host, resource_root))
+            .device_name(device_name)
+            .connection_options(connection_options)
+            .build()
+    }
+
+    /// Connects to the eSCL service at the given URL, e.g.
+    /// `https://scanner:8443/eSCL`.
+    pub fn from_url(base_url: &str) -> Result<Scanner, ScannerError> {
+        ScannerBuilder::new(base_url).build()
+    }
+
+    pub fn builder(base_url: &str) -> ScannerBuilder {
+        ScannerBuilder::new(base_url)
     }
 
     pub fn get_status(&self) -> Result<structs::ScannerState, ScannerError> {
+        let scanner_status = self.get_full_status()?;
+        log::info!("Scanner state: {}", scanner_status.state);
+
+        Ok(scanner_status.state)
+    }
+
+    /// Queries the scanner state along with the feeder state and the jobs the
+    /// scanner still remembers.
+    pub fn get_full_status(&self) -> Result<structs::ScannerStatus, ScannerError> {
         log::info!("Getting scanner status");
-        let response = reqwest::blocking::get(&format!("{}/ScannerStatus", self.base_url))?;
+        let response = auth::send(self.credentials(), || {
+            self.client.get(format!("{}/ScannerStatus", self.base_url))
+        })?;
         log::debug!("ScannerStatus: {:?}", response);
 
-        let response_string = response.text().expect("text is a string");
+        let response_string = response.text()?;
         log::debug!("ScannerStatus: {:?}", response_string);
 
         let scanner_status: structs::ScannerStatus = serde_xml_rs::from_str(&response_string)?;
-        log::info!("Scanner state: {}", scanner_status.state);
+        Ok(scanner_status)
+    }
 
-        Ok(scanner_status.state)
+    /// Checks that the scanner is idle and, for feeder scans, that paper is
+    /// loaded. A busy scanner is waited for as long as the retry policy
+    /// allows. Scanning does this by itself, this allows for checking early.
+    pub fn preflight(&self, scan_settings: &structs::ScanSettings) -> Result<(), ScannerError> {
+        let start = Instant::now();
+        while let Some(delay) = Self::preflight_delay(
+            &self.retry_policy,
+            &self.get_full_status()?,
+            &scan_settings.input_source,
+            start,
+        )? {
+            std::thread::sleep(delay);
+        }
+
+        Ok(())
     }
 
-    pub fn make_settings(&self) -> structs::ScanSettings {
-        structs::ScanSettings {
-            version: "2.6".to_string(),
-            scan_regions: structs::ScanRegion {
+    // Returns how long to wait before checking the status again, or None if
+    // the scanner is ready. Shared with AsyncScanner.
+    pub(crate) fn preflight_delay(
+        retry_policy: &RetryPolicy,
+        status: &structs::ScannerStatus,
+        input_source: &structs::InputSource,
+        start: Instant,
+    ) -> Result<Option<Duration>, ScannerError> {
+        let err = match status.check_ready(input_source) {
+            Ok(()) => return Ok(None),
+            Err(err) => err,
+        };
+
+        // Feeder problems need someone to fix them, waiting won't help
+        if !matches!(err.code, ErrorCode::ScannerNotReady) {
+            return Err(err);
+        }
+
+        match retry_policy.poll_delay(start.elapsed()) {
+            Some(delay) => {
+                log::info!("{err}, checking again in {delay:?}");
+                Ok(Some(delay))
+            }
+            None => Err(err),
+        }
+    }
+
+    pub fn make_settings(
+        &self,
+        input_source: structs::InputSource,
+    ) -> Result<structs::ScanSettings, ScannerError> {
+        // Scanners without the requested input source will reject the job
+        // anyway, the platen dimensions are as good a default as any then.
+        let capabilities = self.capabilities()?;
+        let input_caps = capabilities
+            .input_caps(&input_source, false)
+            .unwrap_or(&capabilities.platen.platen_input_caps);
+
+        Ok(structs::ScanSettings::new(
+            input_source,
+            structs::ScanRegion {
                 x_offset: 0,
                 y_offset: 0,
-                width: self.capabilities.platen.platen_input_caps.max_width,
-                height: self.capabilities.platen.platen_input_caps.max_height,
+                width: input_caps.max_width,
+                height: input_caps.max_height,
                 content_region_units: "escl:ThreeHundredthsOfInches".to_string(),
             },
-            content_type: "Auto".to_string(),
-            input_source: "Platen".to_string(),
-            color_mode: "RGB24".to_string(),
-            document_format: "image/jpeg".to_string(),
-            feed_direction: structs::FeedDirection::ShortEdgeFeed.into(),
-            x_resolution: 300,
-            y_resolution: 300,
-        }
+        ))
     }
 
+    /// Adjusts the given settings to the closest ones this scanner supports.
+    pub fn negotiate_settings(
+        &self,
+        scan_settings: &mut structs::ScanSettings,
+    ) -> Result<Vec<SettingsAdjustment>, ScannerError> {
+        Ok(scan_settings.negotiate(self.capabilities()?))
+    }
+
+    /// `page_order` tells how a duplex feeder delivers the pages, see
+    /// `PageOrder`.
     pub fn scan(
         &self,
         scan_settings: &structs::ScanSettings,
+        page_order: PageOrder,
         destination_file: &str,
     ) -> Result<(), ScannerError> {
-        let request_body = serde_xml_rs::to_string(scan_settings)?;
-
-        log::info!("Sending scan request with settings: {:?}", scan_settings);
-        let client = reqwest::blocking::Client::new();
-        let request = client
-            .post(format!("{}/ScanJobs", &self.base_url).as_str())
-            .body(format!(
-                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>{}",
-                request_body
-            ));
-        log::debug!("< ScanJobs: {request:#?}\nBody: {request_body:#?}");
-
-        let response = request.send()?;
-        log::debug!("> ScanJobs: {response:#?}");
-
-        if response.status().is_client_error() || response.status().is_server_error() {
-            return Err(ScannerError {
-                code: ErrorCode::NetworkError,
-                message: format!(
-                    "Status Code: {:?}, Text: {}",
-                    response.status(),
-                    response.text()?
-                ),
-            });
-        }
-
-        let location = match response.headers().get("location") {
-            Some(location) => location.to_str().expect("'location' can be a string"),
-            None => {
-                return Err(ScannerError {
-                    code: ErrorCode::ProtocolError,
-                    message: format!(
-                        "Failed to get 'location' header from response:\n{response:#?}"
-                    ),
-                });
-            }
-        };
-
-        let download_url = format!("{}/NextDocument", location);
-        return self.download_scanned_pages(&scan_settings, &download_url, destination_file);
+        self.scan_to(
+            scan_settings,
+            page_order,
+            &mut FileSink::new(destination_file),
+        )
     }
 
-    fn download_scanned_pages(
+    /// Scans and hands the pages to the given sink. Pages are passed on as
+    /// soon as they arrive unless they need to be reordered.
+    pub fn scan_to(
         &self,
         scan_settings: &structs::ScanSettings,
-        download_url: &str,
-        destination_file: &str,
+        page_order: PageOrder,
+        sink: &mut dyn PageSink,
     ) -> Result<(), ScannerError> {
-        // We need to try downloadng pages until we get a 404 for the printer to
-        // consider the scan job done.
-        // This is necessary on my Brother MFC-L2710DW to get it to idle state
-        // again. It will wait for timeout otherwise, even if we got the scanned
-        // page earlier.
+        self.preflight(scan_settings)?;
 
-        let mut new_page_idx: u16 = 1;
-        loop {
-            let tmp_page_path = std::env::temp_dir().join(Uuid::new_v4().to_string());
-            log::info!(
-                "Downloading page {} to {}",
-                new_page_idx,
-                tmp_page_path.to_str().expect("File path is printable")
-            );
-            let mut tmp_page_file = fs::File::create(&tmp_page_path)?;
-
-            if let Err(err) = self.download_scanned_page(download_url, &mut tmp_page_file) {
-                match err.code {
-                    ErrorCode::NoMorePages => {
-                        if new_page_idx == 1 {
-                            log::error!("Scanner has no pages available for download at all");
-                            return Err(err);
-                        } else {
-                            log::info!("There is no page {new_page_idx}, we're done");
-                            return Ok(());
-                        }
-                    }
-                    _ => return Err(err),
-                }
-            }
+        if page_order != PageOrder::AsDelivered {
+            let pages = self.scan_pages(scan_settings)?;
+            let pages = page_order.arrange(pages);
+            return Self::store_scanned_pages(scan_settings, pages, sink);
+        }
 
-            if let Err(err) = self.process_scanned_page(
-                scan_settings,
-                &tmp_page_path,
-                destination_file,
-                new_page_idx,
-            ) {
-                fs::remove_file(tmp_page_path)?;
-                return Err(err);
+        let mut job = self.create_scan_job(scan_settings)?;
+        let mut page_count = 0;
+        loop {
+            let page = match job.next_page() {
+                Ok(Some(page)) => page,
+                Ok(None) => break,
+                Err(err) => return Err(job.abort(err)),
+            };
+            page_count += 1;
+            if let Err(err) = sink.store_page(&PageInfo::new(scan_settings, page_count), page) {
+                return Err(job.abort(err));
             }
-
-            new_page_idx += 1;
         }
-    }
 
-    fn download_scanned_page(
-        &self,
-        download_url: &str,
-        destination_file: &mut fs::File,
-    ) -> Result<(), ScannerError> {
-        let mut response = reqwest::blocking::get(download_url)?;
-        if response.status() == 404 {
+        if page_count == 0 {
+            log::error!("Scanner has no pages available for download at all");
             return Err(ScannerError {
                 code: ErrorCode::NoMorePages,
                 message: String::new(),
             });
         }
 
-        response.copy_to(destination_file)?;
-        Ok(())
+        sink.finish()
     }
 
-    fn process_scanned_page(
+    /// Scans both sides of a stack of sheets with a simplex feeder: All front
+    /// sides are scanned first, then `flip_stack` is called to let the user
+    /// turn the stack over and the back sides are scanned. Returning false
+    /// from `flip_stack` aborts the scan.
+    ///
+    /// If the number of front and back sides differs, e.g. because two sheets
+    /// were pulled in at once, all pages are stored in the order they were
+    /// scanned and PageCountMismatch is returned.
+    pub fn scan_manual_duplex<F>(
         &self,
         scan_settings: &structs::ScanSettings,
-        tmp_page_path: &Path,
         destination_file: &str,
-        page_idx: u16,
-    ) -> Result<(), ScannerError> {
-        // This could be more elegant if I managed to (de)serialize the format
-        // to/from an enum...
-        if scan_settings.document_format.contains("pdf") {
-            if Path::new(destination_file).exists() {
-                log::info!("Appending page to existing document {destination_file}");
-                self.merge_scanned_page(Path::new(destination_file), tmp_page_path)?;
-            } else {
-                log::info!("Storing scanned page as {destination_file}");
-                fs::rename(tmp_page_path, &destination_file)?;
-            }
-        } else {
-            let page_file_name = self.make_jpg_file_name(&destination_file, page_idx)?;
-            log::info!("Storing scanned page as {page_file_name}");
-            fs::rename(tmp_page_path, &page_file_name)?;
-        }
-
-        Ok(())
+        flip_stack: F,
+    ) -> Result<(), ScannerError>
+    where
+        F: FnOnce() -> bool,
+    {
+        self.scan_manual_duplex_to(
+            scan_settings,
+            &mut FileSink::new(destination_file),
+            flip_stack,
+        )
     }
 
-    fn make_jpg_file_name(
+    /// Like `scan_manual_duplex()`, but hands the pages to the given sink.
+    pub fn scan_manual_duplex_to<F>(
         &self,
-        destination_file: &str,
-        new_page_idx: u16,
-    ) -> Result<String, ScannerError> {
-        if !Path::new(destination_file).exists() {
-            log::info!("Destination file does not exist yet");
-            return Ok(destination_file.into());
+        scan_settings: &structs::ScanSettings,
+        sink: &mut dyn PageSink,
+        flip_stack: F,
+    ) -> Result<(), ScannerError>
+    where
+        F: FnOnce() -> bool,
+    {
+        // Only the feeder takes the stack after it was turned over
+        if scan_settings.input_source != structs::InputSource::Feeder {
+            return Err(ScannerError {
+                code: ErrorCode::InvalidSettings,
+                message: "Manual duplex scanning needs the feeder as input source".to_string(),
+            });
         }
 
-        // We cannot write to destination_file, try numbering pages
-        if let Some(last_dot_pos) = destination_file.rfind('.') {
-            let (path_part, ext_part) = destination_file.split_at(last_dot_pos);
+        log::info!("Scanning front sides");
+        self.preflight(scan_settings)?;
+        let fronts = self.scan_pages(scan_settings)?;
 
-            let page_file_name = format!("{path_part}_{new_page_idx}{ext_part}");
-            if !Path::new(&page_file_name).exists() {
-                log::info!("Created page file name \"{page_file_name}\"");
-                return Ok(page_file_name);
-            }
+        if !flip_stack() {
+            return Err(ScannerError {
+                code: ErrorCode::Aborted,
+                message: String::new(),
+            });
+        }
 
-            let numbered_path_part = format!("{path_part}_{new_page_idx}");
-            for i in 1..u16::MAX {
-                let fallback_numbered_file_name = format!("{numbered_path_part}_{i}{ext_part}");
-                if !Path::new(&fallback_numbered_file_name).exists() {
-                    log::info!("Created fallback file name \"{fallback_numbered_file_name}\"");
-                    return Ok(fallback_numbered_file_name);
-                }
-            }
+        log::info!("Scanning back sides");
+        self.preflight(scan_settings)?;
+        let backs = self.scan_pages(scan_settings)?;
 
+        if fronts.len() != backs.len() {
+            let message = format!(
+                "Scanned {} front sides, but {} back sides",
+                fronts.len(),
+                backs.len()
+            );
+            // Which back belongs to which front is unclear now, but the
+            // user should not have to scan everything again
+            let pages = fronts.into_iter().chain(backs).collect();
+            Self::store_scanned_pages(scan_settings, pages, sink)?;
             return Err(ScannerError {
-                code: ErrorCode::FilesystemError,
-                message: format!(
-                    "Failed to determine an alternative to existing destination file: \"{}\"",
-                    destination_file.to_string()
-                ),
+                code: ErrorCode::PageCountMismatch,
+                message,
             });
         }
 
-        Err(ScannerError {
-            code: ErrorCode::NoFileExtension,
-            message: destination_file.to_string(),
-        })
+        // The stack was turned over, so the last sheet's back side came first
+        let pages = interleave_pages(fronts, backs, true);
+        Self::store_scanned_pages(scan_settings, pages, sink)
     }
 
-    // Modelled after the lopdf example: https://crates.io/crates/lopdf
-    // This must be doable by extending the existing document, too!
-    fn merge_scanned_page(&self, output_path: &Path, page_path: &Path) -> Result<(), ScannerError> {
-        assert!(output_path.is_file());
-        assert!(page_path.is_file());
-
-        let documents = vec![Document::load(output_path)?, Document::load(page_path)?];
-
-        // Define a starting max_id (will be used as start index for object_ids)
-        let mut max_id = 1;
-        let mut pagenum = 1;
-        // Collect all Documents Objects grouped by a map
-        let mut documents_pages = BTreeMap::new();
-        let mut documents_objects = BTreeMap::new();
-        let mut document = Document::with_version("1.5");
-
-        for mut doc in documents {
-            let mut first = false;
-            doc.renumber_objects_with(max_id);
-
-            max_id = doc.max_id + 1;
-
-            documents_pages.extend(
-                doc.get_pages()
-                    .into_iter()
-                    .map(|(_, object_id)| {
-                        if !first {
-                            let bookmark = Bookmark::new(
-                                String::from(format!("Page_{}", pagenum)),
-                                [0.0, 0.0, 1.0],
-                                0,
-                                object_id,
-                            );
-                            document.add_bookmark(bookmark, None);
-                            first = true;
-                            pagenum += 1;
-                        }
-
-                        (object_id, doc.get_object(object_id).unwrap().to_owned())
-                    })
-                    .collect::<BTreeMap<ObjectId, Object>>(),
-            );
-            documents_objects.extend(doc.objects);
-        }
+    /// Starts a scan job. Its pages must be fetched until `ScanJob::next_page()`
+    /// returns None, or the job must be cancelled, before the scanner accepts
+    /// the next one.
+    pub fn create_scan_job(
+        &self,
+        scan_settings: &structs::ScanSettings,
+    ) -> Result<ScanJob, ScannerError> {
+        let request_body = Self::make_scan_request_body(scan_settings, self.capabilities()?)?;
 
-        // Catalog and Pages are mandatory
-        let mut catalog_object: Option<(ObjectId, Object)> = None;
-        let mut pages_object: Option<(ObjectId, Object)> = None;
-
-        // Process all objects except "Page" type
-        for (object_id, object) in documents_objects.iter() {
-            // We have to ignore "Page" (as are processed later), "Outlines" and "Outline" objects
-            // All other objects should be collected and inserted into the main Document
-            match object.type_name().unwrap_or("") {
-                "Catalog" => {
-                    // Collect a first "Catalog" object and use it for the future "Pages"
-                    catalog_object = Some((
-                        if let Some((id, _)) = catalog_object {
-                            id
-                        } else {
-                            *object_id
-                        },
-                        object.clone(),
-                    ));
-                }
-                "Pages" => {
-                    // Collect and update a first "Pages" object and use it for the future "Catalog"
-                    // We have also to merge all dictionaries of the old and the new "Pages" object
-                    if let Ok(dictionary) = object.as_dict() {
-                        let mut dictionary = dictionary.clone();
-                        if let Some((_, ref object)) = pages_object {
-                            if let Ok(old_dictionary) = object.as_dict() {
-                                dictionary.extend(old_dictionary);
-                            }
-                        }
-
-                        pages_object = Some((
-                            if let Some((id, _)) = pages_object {
-                                id
-                            } else {
-                                *object_id
-                            },
-                            Object::Dictionary(dictionary),
-                        ));
-                    }
-                }
-                "Page" => {}     // Ignored, processed later and separately
-                "Outlines" => {} // Ignored, not supported yet
-                "Outline" => {}  // Ignored, not supported yet
-                _ => {
-                    document.objects.insert(*object_id, object.clone());
-                }
-            }
-        }
+        log::info!("Sending scan request with settings: {:?}", scan_settings);
+        let client = self.client.clone();
+        log::debug!("< ScanJobs: {request_body:#?}");
+        let response = self.retry_policy.send(self.credentials(), || {
+            client
+                .post(format!("{}/ScanJobs", &self.base_url).as_str())
+                .body(request_body.clone())
+        })?;
+        log::debug!("> ScanJobs: {response:#?}");
 
-        // If no "Pages" object found abort
-        if pages_object.is_none() {
-            println!("Pages root not found.");
-            return Ok(());
+        if response.status().is_client_error() || response.status().is_server_error() {
+            return Err(Self::make_status_error(
+                response.status(),
+                &response.text()?,
+            ));
         }
 
-        // Iterate over all "Page" objects and collect into the parent "Pages" created before
-        for (object_id, object) in documents_pages.iter() {
-            if let Ok(dictionary) = object.as_dict() {
-                let mut dictionary = dictionary.clone();
-                dictionary.set("Parent", pages_object.as_ref().unwrap().0);
-                document
-                    .objects
-                    .insert(*object_id, Object::Dictionary(dictionary));
+        let job_url = Self::make_job_url(&self.base_url, response.headers())?;
+        Ok(ScanJob::new(
+            client,
+            job_url,
+            self.retry_policy.clone(),
+            self.connection_options.credentials.clone(),
+        ))
+    }
+
+    // Runs a scan job and returns its pages
+    fn scan_pages(
+        &self,
+        scan_settings: &structs::ScanSettings,
+    ) -> Result<Vec<Vec<u8>>, ScannerError> {
+        let mut job = self.create_scan_job(scan_settings)?;
+
+        // We need to try downloadng pages until we get a 404 for the printer to
+        // consider the scan job done.
+        // This is necessary on my Brother MFC-L2710DW to get it to idle state
+        // again. It will wait for timeout otherwise, even if we got the scanned
+        // page earlier.
+        let mut pages = vec![];
+        loop {
+            match job.next_page() {
+                Ok(Some(page)) => pages.push(page),
+                Ok(None) => break,
+                Err(err) => return Err(job.abort(err)),
             }
         }
 
-        // If no "Catalog" found abort
-        if catalog_object.is_none() {
-            println!("Catalog root not found.");
-            return Ok(());
+        if pages.is_empty() {
+            log::error!("Scanner has no pages available for download at all");
+            return Err(ScannerError {
+                code: ErrorCode::NoMorePages,
+                message: String::new(),
+            });
         }
 
-        let catalog_object = catalog_object.unwrap();
-        let pages_object = pages_object.unwrap();
-
-        // Build a new "Pages" with updated fields
-        if let Ok(dictionary) = pages_object.1.as_dict() {
-            let mut dictionary = dictionary.clone();
+        Ok(pages)
+    }
 
-            // Set new pages count
-            dictionary.set("Count", documents_pages.len() as u32);
+    fn store_scanned_pages(
+        scan_settings: &structs::ScanSettings,
+        pages: Vec<Vec<u8>>,
+        sink: &mut dyn PageSink,
+    ) -> Result<(), ScannerError> {
+        for (idx, page) in pages.into_iter().enumerate() {
+            sink.store_page(&PageInfo::new(scan_settings, idx + 1), page)?;
+        }
 
-            // Set new "Kids" list (collected from documents pages) for "Pages"
-            dictionary.set(
-                "Kids",
-                documents_pages
-                    .into_iter()
-                    .map(|(object_id, _)| Object::Reference(object_id))
-                    .collect::<Vec<_>>(),
-            );
+        sink.finish()
+    }
+}
 
-            document
-                .objects
-                .insert(pages_object.0, Object::Dictionary(dictionary));
+impl Display for Scanner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n- URL: {}", self.device_name, self.base_url)?;
+        if let Some(advertisement) = &self.advertisement {
+            write!(f, "{advertisement}")?;
         }
-
-        // Build a new "Catalog" with updated fields
-        if let Ok(dictionary) = catalog_object.1.as_dict() {
-            let mut dictionary = dictionary.clone();
-            dictionary.set("Pages", pages_object.0);
-            dictionary.remove(b"Outlines"); // Outlines not supported in merged PDFs
-            document
-                .objects
-                .insert(catalog_object.0, Object::Dictionary(dictionary));
+        match self.capabilities.get() {
+            Some(capabilities) => write!(f, "\n- Capabilities: {capabilities:#?}"),
+            None => Ok(()),
         }
+    }
+}
 
-        document.trailer.set("Root", catalog_object.0);
-
-        // Update the max internal ID as wasn't updated before due to direct objects insertion
-        document.max_id = document.objects.len() as u32;
-
-        // Reorder all new Document objects
-        document.renumber_objects();
+#[cfg(test)]
+mod tests {
+    use crate::{
+        pageorder::PageOrder,
+        pagesink::MemorySink,
+        scanner::Scanner,
+        scannererror::ErrorCode,
+        structs::InputSource,
+        testutil::{failing_scanner, fake_scanner, scan_settings},
+    };
+
+    fn pages(contents: &[&str]) -> Vec<Vec<u8>> {
+        contents
+            .iter()
+            .map(|content| content.as_bytes().to_vec())
+            .collect()
+    }
 
-        //Set any Bookmarks to the First child if they are not set to a page
-        document.adjust_zero_pages();
+    fn stored_pages(sink: &MemorySink) -> Vec<String> {
+        sink.pages
+            .iter()
+            .map(|(_, page)| String::from_utf8(page.clone()).unwrap())
+            .collect()
+    }
 
-        //Set all bookmarks to the PDF Object tree then set the Outlines to the Bookmark content map.
-        if let Some(n) = document.build_outline() {
-            if let Ok(x) = document.get_object_mut(catalog_object.0) {
-                if let Object::Dictionary(ref mut dict) = x {
-                    dict.set("Outlines", Object::Reference(n));
-                }
-            }
+    #[test]
+    fn failed_download_cancels_job() {
+        let server = failing_scanner();
+        let scanner = Scanner::from_url(&format!("{}/eSCL", server.url)).unwrap();
+
+        // Streamed as delivered and collected for reordering
+        for page_order in [PageOrder::AsDelivered, PageOrder::FrontsThenBacks] {
+            let err = scanner
+                .scan_to(
+                    &scan_settings(InputSource::Feeder),
+                    page_order,
+                    &mut MemorySink::default(),
+                )
+                .unwrap_err();
+            assert!(matches!(err.code, ErrorCode::NetworkError));
         }
+        assert!(
+            server
+                .requests()
+                .iter()
+                .filter(|request| *request == "DELETE /eSCL/ScanJobs/1")
+                .count()
+                == 2
+        );
+    }
 
-        document.compress();
-        if let Err(err) = document.save(output_path) {
-            log::error!("Failed to save merged pdf document: {err:?}");
-            return Err(err.into());
-        }
+    #[test]
+    fn manual_duplex() {
+        let server = fake_scanner(vec![
+            pages(&["front 1", "front 2"]),
+            pages(&["back 2", "back 1"]),
+        ]);
+        let scanner = Scanner::from_url(&format!("{}/eSCL", server.url)).unwrap();
+
+        let mut sink = MemorySink::default();
+        let mut flipped = false;
+        scanner
+            .scan_manual_duplex_to(&scan_settings(InputSource::Feeder), &mut sink, || {
+                flipped = true;
+                true
+            })
+            .unwrap();
+        assert!(flipped);
+        assert!(stored_pages(&sink) == ["front 1", "back 1", "front 2", "back 2"]);
+    }
 
-        Ok(())
+    #[test]
+    fn manual_duplex_page_count_mismatch() {
+        let server = fake_scanner(vec![pages(&["front 1", "front 2"]), pages(&["back 2"])]);
+        let scanner = Scanner::from_url(&format!("{}/eSCL", server.url)).unwrap();
+
+        let mut sink = MemorySink::default();
+        let err = scanner
+            .scan_manual_duplex_to(&scan_settings(InputSource::Feeder), &mut sink, || true)
+            .unwrap_err();
+        assert!(matches!(err.code, ErrorCode::PageCountMismatch));
+        assert!(stored_pages(&sink) == ["front 1", "front 2", "back 2"]);
     }
-}
 
-impl Display for Scanner {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}
-- URL: {}
-- Capabilities: {:#?}",
-            self.device_name, self.base_url, self.capabilities
-        )
+    #[test]
+    fn manual_duplex_needs_feeder() {
+        let server = fake_scanner(vec![pages(&["front 1"])]);
+        let scanner = Scanner::from_url(&format!("{}/eSCL", server.url)).unwrap();
+
+        let mut sink = MemorySink::default();
+        let err = scanner
+            .scan_manual_duplex_to(&scan_settings(InputSource::Platen), &mut sink, || {
+                panic!("there is no stack to turn over")
+            })
+            .unwrap_err();
+        assert!(matches!(err.code, ErrorCode::InvalidSettings));
+        assert!(!server
+            .requests()
+            .contains(&"POST /eSCL/ScanJobs".to_string()));
     }
 }
@@ -0,0 +1,164 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{collections::HashMap, fmt::Display};
+
+use crate::structs::{DocumentFormat, InputSource};
+
+/// What a scanner announces about itself in its mDNS TXT record. All keys
+/// are optional, scanners differ in what they send.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScannerAdvertisement {
+    /// eSCL version (vers)
+    pub version: Option<String>,
+    /// Device UUID, also found in the capabilities (UUID)
+    pub uuid: Option<String>,
+    /// Human-readable device name (ty)
+    pub device_name: Option<String>,
+    /// Path of the eSCL service (rs)
+    pub resource_root: Option<String>,
+    /// Supported document formats (pdl)
+    pub document_formats: Vec<DocumentFormat>,
+    /// Supported color spaces like "color", "grayscale" or "binary" (cs)
+    pub color_spaces: Vec<String>,
+    /// Supported input sources (is)
+    pub input_sources: Vec<InputSource>,
+    /// Whether the feeder can scan both sides (duplex)
+    pub duplex: Option<bool>,
+    /// URL of an icon of the device (representation)
+    pub icon_url: Option<String>,
+    /// URL of the device's web interface (adminurl)
+    pub admin_url: Option<String>,
+    /// Where the device is located, as configured by the admin (note)
+    pub location: Option<String>,
+}
+
+impl ScannerAdvertisement {
+    pub fn from_txt(txt: &HashMap<String, String>) -> ScannerAdvertisement {
+        // Keys are case insensitive, values may be empty
+        let value = |key: &str| {
+            txt.iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.trim())
+                .filter(|value| !value.is_empty())
+        };
+        let string = |key: &str| value(key).map(str::to_string);
+        let list = |key: &str| -> Vec<&str> {
+            value(key)
+                .map(|value| value.split(',').map(str::trim).collect())
+                .unwrap_or_default()
+        };
+
+        ScannerAdvertisement {
+            version: string("vers"),
+            uuid: string("UUID"),
+            device_name: string("ty"),
+            resource_root: string("rs"),
+            document_formats: list("pdl").into_iter().map(DocumentFormat::from).collect(),
+            color_spaces: list("cs").into_iter().map(str::to_string).collect(),
+            input_sources: list("is")
+                .into_iter()
+                .map(|source| match source.to_ascii_lowercase().as_str() {
+                    "platen" => InputSource::Platen,
+                    "adf" => InputSource::Feeder,
+                    "camera" => InputSource::Camera,
+                    _ => InputSource::Other(source.to_string()),
+                })
+                .collect(),
+            duplex: value("duplex").map(|duplex| matches!(duplex, "T" | "t" | "true")),
+            icon_url: string("representation"),
+            admin_url: string("adminurl"),
+            location: string("note"),
+        }
+    }
+}
+
+impl Display for ScannerAdvertisement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let join = |values: Vec<String>| values.join(", ");
+
+        if let Some(location) = &self.location {
+            write!(f, "\n- Location: {location}")?;
+        }
+        if !self.input_sources.is_empty() {
+            let sources = join(self.input_sources.iter().map(|s| s.to_string()).collect());
+            write!(f, "\n- Input sources: {sources}")?;
+        }
+        if let Some(duplex) = self.duplex {
+            write!(f, "\n- Duplex: {}", if duplex { "yes" } else { "no" })?;
+        }
+        if !self.color_spaces.is_empty() {
+            write!(f, "\n- Color spaces: {}", join(self.color_spaces.clone()))?;
+        }
+        if !self.document_formats.is_empty() {
+            let formats = join(
+                self.document_formats
+                    .iter()
+                    .map(|f| f.to_string())
+                    .collect(),
+            );
+            write!(f, "\n- Formats: {formats}")?;
+        }
+        if let Some(admin_url) = &self.admin_url {
+            write!(f, "\n- Admin URL: {admin_url}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        scanneradvertisement::ScannerAdvertisement,
+        structs::{DocumentFormat, InputSource},
+    };
+
+    #[test]
+    fn brother_advertisement() {
+        let txt: HashMap<String, String> = [
+            ("txtvers", "1"),
+            ("ty", "Brother MFC-L2710DW series"),
+            ("note", "Office, 2nd floor"),
+            (
+                "adminurl",
+                "http://BRW3C2AF4493199.local./net/net/airprint.html",
+            ),
+            (
+                "representation",
+                "http://BRW3C2AF4493199.local./icons/device-icons-128.png",
+            ),
+            ("rs", "eSCL"),
+            ("vers", "2.63"),
+            ("pdl", "application/pdf,image/jpeg"),
+            ("UUID", "e3248000-80ce-11db-8000-3c2af4493199"),
+            ("cs", "binary,grayscale,color"),
+            ("is", "platen,adf"),
+            ("duplex", "F"),
+            ("kind", ""),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        let advertisement = ScannerAdvertisement::from_txt(&txt);
+        assert!(advertisement.version.as_deref() == Some("2.63"));
+        assert!(advertisement.uuid.as_deref() == Some("e3248000-80ce-11db-8000-3c2af4493199"));
+        assert!(advertisement.device_name.as_deref() == Some("Brother MFC-L2710DW series"));
+        assert!(advertisement.resource_root.as_deref() == Some("eSCL"));
+        assert!(advertisement.document_formats == vec![DocumentFormat::Pdf, DocumentFormat::Jpeg]);
+        assert!(advertisement.color_spaces == vec!["binary", "grayscale", "color"]);
+        assert!(advertisement.input_sources == vec![InputSource::Platen, InputSource::Feeder]);
+        assert!(advertisement.duplex == Some(false));
+        assert!(advertisement.location.as_deref() == Some("Office, 2nd floor"));
+        assert!(advertisement.admin_url.is_some());
+        assert!(advertisement.icon_url.is_some());
+
+        assert!(ScannerAdvertisement::from_txt(&HashMap::new()) == ScannerAdvertisement::default());
+    }
+}
@@ -0,0 +1,174 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::{Arc, OnceLock};
+
+use crate::{
+    capscache::CapabilitiesCache,
+    connection::ConnectionOptions,
+    retry::RetryPolicy,
+    scanner::Scanner,
+    scanneradvertisement::ScannerAdvertisement,
+    scannererror::{ErrorCode, ScannerError},
+};
+
+/// Creates a `Scanner` for the eSCL service at a given URL, e.g.
+/// `https://scanner:8443/eSCL`.
+pub struct ScannerBuilder {
+    base_url: String,
+    device_name: Option<String>,
+    connection_options: ConnectionOptions,
+    client: Option<reqwest::blocking::Client>,
+    retry_policy: RetryPolicy,
+    capabilities_cache: Option<Arc<CapabilitiesCache>>,
+    uuid: Option<String>,
+    version: Option<String>,
+    advertisement: Option<ScannerAdvertisement>,
+    lazy: bool,
+}
+
+impl ScannerBuilder {
+    pub fn new(base_url: &str) -> ScannerBuilder {
+        ScannerBuilder {
+            base_url: base_url.to_string(),
+            device_name: None,
+            connection_options: ConnectionOptions::default(),
+            client: None,
+            retry_policy: RetryPolicy::default(),
+            capabilities_cache: None,
+            uuid: None,
+            version: None,
+            advertisement: None,
+            lazy: false,
+        }
+    }
+
+    /// Defaults to the host name from the URL.
+    pub fn device_name(mut self, device_name: &str) -> ScannerBuilder {
+        self.device_name = Some(device_name.to_string());
+        self
+    }
+
+    /// Certificate options for the client and credentials. Scheme and port
+    /// are taken from the URL.
+    pub fn connection_options(mut self, connection_options: ConnectionOptions) -> ScannerBuilder {
+        self.connection_options = connection_options;
+        self
+    }
+
+    /// Uses a preconfigured client, e.g. with timeouts, a proxy or default
+    /// headers. Certificate options are ignored then.
+    pub fn client(mut self, client: reqwest::blocking::Client) -> ScannerBuilder {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> ScannerBuilder {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Looks up the capabilities in the cache before asking the scanner, and
+    /// stores fetched ones there. Requires the UUID to be known up front.
+    pub fn capabilities_cache(mut self, cache: Arc<CapabilitiesCache>) -> ScannerBuilder {
+        self.capabilities_cache = Some(cache);
+        self
+    }
+
+    /// UUID and eSCL version of the scanner, as announced via mDNS.
+    pub fn uuid(mut self, uuid: &str, version: Option<&str>) -> ScannerBuilder {
+        self.uuid = Some(uuid.to_string());
+        self.version = version.map(str::to_string);
+        self
+    }
+
+    /// Keeps the mDNS announcement of the scanner. Its UUID and version are
+    /// used unless given with `uuid()`.
+    pub fn advertisement(mut self, advertisement: ScannerAdvertisement) -> ScannerBuilder {
+        self.advertisement = Some(advertisement);
+        self
+    }
+
+    /// Fetches the capabilities on first use instead of in `build()`.
+    pub fn lazy(mut self, lazy: bool) -> ScannerBuilder {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Connects to the scanner and fetches its capabilities, unless in lazy
+    /// mode.
+    pub fn build(self) -> Result<Scanner, ScannerError> {
+        let url = parse_base_url(&self.base_url)?;
+        let connection_options = ConnectionOptions {
+            secure: url.scheme() == "https",
+            port: url.port_or_known_default(),
+            ..self.connection_options
+        };
+        let client = match self.client {
+            Some(client) => client,
+            None => connection_options.make_client()?,
+        };
+
+        let scanner = Scanner {
+            device_name: self
+                .device_name
+                .unwrap_or_else(|| url.host_str().unwrap_or_default().to_string()),
+            base_url: self.base_url.trim_end_matches('/').to_string(),
+            retry_policy: self.retry_policy,
+            connection_options,
+            client,
+            capabilities: OnceLock::new(),
+            capabilities_cache: self.capabilities_cache,
+            uuid: self
+                .uuid
+                .or_else(|| self.advertisement.as_ref()?.uuid.clone()),
+            version: self
+                .version
+                .or_else(|| self.advertisement.as_ref()?.version.clone()),
+            advertisement: self.advertisement,
+        };
+
+        if !self.lazy {
+            scanner.capabilities()?;
+        }
+
+        Ok(scanner)
+    }
+}
+
+pub(crate) fn parse_base_url(base_url: &str) -> Result<reqwest::Url, ScannerError> {
+    let url = reqwest::Url::parse(base_url).map_err(|err| ScannerError {
+        code: ErrorCode::InvalidUrl,
+        message: format!("{base_url}: {err}"),
+    })?;
+
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(ScannerError {
+            code: ErrorCode::InvalidUrl,
+            message: format!("{base_url}: Only http and https URLs are supported"),
+        });
+    }
+
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scannerbuilder::parse_base_url;
+
+    #[test]
+    fn base_urls() {
+        let url = parse_base_url("https://scanner:8443/eSCL").expect("URL is valid");
+        assert!(url.host_str() == Some("scanner"));
+        assert!(url.port_or_known_default() == Some(8443));
+
+        let url = parse_base_url("http://192.168.1.5/eSCL/").expect("URL is valid");
+        assert!(url.port_or_known_default() == Some(80));
+
+        assert!(parse_base_url("scanner/eSCL").is_err());
+        assert!(parse_base_url("ftp://scanner/eSCL").is_err());
+    }
+}
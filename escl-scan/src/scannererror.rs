@@ -8,11 +8,21 @@ use core::fmt;
 
 #[derive(Debug)]
 pub enum ErrorCode {
+    Aborted,
+    AuthenticationFailed,
+    FeederEmpty,
+    FeederJam,
+    FeederNotReady,
+    FeederOpen,
     FilesystemError,
+    InvalidAddress,
+    InvalidSettings,
+    InvalidUrl,
     NetworkError,
     NoFileExtension,
     NoMorePages,
     NoScannerFound,
+    PageCountMismatch,
     PdfError,
     ProtocolError,
     ScannerNotReady,
@@ -27,7 +37,23 @@ pub struct ScannerError {
 impl fmt::Display for ScannerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let msg = match self.code {
+            ErrorCode::Aborted => "The scan was aborted".to_string(),
+            ErrorCode::AuthenticationFailed => {
+                format!("Authentication failed: {}", self.message)
+            }
+            ErrorCode::FeederEmpty => "There is no paper in the feeder".to_string(),
+            ErrorCode::FeederJam => format!("The feeder is jammed: {}", self.message),
+            ErrorCode::FeederNotReady => format!("The feeder is not ready: {}", self.message),
+            ErrorCode::FeederOpen => format!("The feeder is open: {}", self.message),
             ErrorCode::FilesystemError => format!("File System Error: {}", self.message),
+            ErrorCode::InvalidAddress => format!("Invalid address: {}", self.message),
+            ErrorCode::InvalidSettings => {
+                format!(
+                    "Scan settings not supported by the scanner: {}",
+                    self.message
+                )
+            }
+            ErrorCode::InvalidUrl => format!("Invalid scanner URL: {}", self.message),
             ErrorCode::NetworkError => format!("Network Error: {}", self.message),
             ErrorCode::NoFileExtension => format!(
                 "Specified output file does not have a file extension: {}",
@@ -39,9 +65,14 @@ impl fmt::Display for ScannerError {
             ErrorCode::NoScannerFound => {
                 format!("No scanner found where name contains \"{}\"", self.message)
             }
+            ErrorCode::PageCountMismatch => {
+                format!("Page count of both sides differs: {}", self.message)
+            }
             ErrorCode::PdfError => format!("PDF processing error: {}", self.message),
             ErrorCode::ProtocolError => format!("eSCL Protocol Error: {}", self.message),
-            ErrorCode::ScannerNotReady => "The scanner is not ready to scan".to_string(),
+            ErrorCode::ScannerNotReady => {
+                format!("The scanner is not ready to scan: {}", self.message)
+            }
         };
 
         write!(f, "{}", msg)
@@ -84,6 +115,7 @@ impl From<std::io::Error> for ScannerError {
     }
 }
 
+#[cfg(feature = "zeroconf")]
 impl From<zeroconf::error::Error> for ScannerError {
     fn from(error: zeroconf::error::Error) -> Self {
         ScannerError {
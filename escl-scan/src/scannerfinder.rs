@@ -5,132 +5,724 @@
  */
 
 use std::{
-    any::Any,
-    sync::{Arc, Mutex},
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
-use zeroconf::{
-    browser::TMdnsBrowser, event_loop::TEventLoop, txt_record::TTxtRecord, MdnsBrowser,
-    ServiceDiscovery, ServiceType, TxtRecord,
-};
-
 use crate::{
+    capscache::CapabilitiesCache,
+    connection::ConnectionOptions,
+    discoveredservice::{deduplicate, AddressFamily, DiscoveredService},
+    discoverybackend::{
+        default_backend, BackendEvent, DiscoveryBackend, DiscoverySession, SERVICE_TYPES,
+    },
+    discoveryoptions::DiscoveryOptions,
+    discoverystream::{DiscoveryEvent, DiscoveryStream},
     scanner::Scanner,
     scannererror::{ErrorCode, ScannerError},
+    scannermonitor::{MonitorOptions, ScannerMonitor},
 };
 
+// Connecting to scanners is slow, but they are independent of each other
+const RESOLVE_WORKERS: usize = 8;
+
+// Refreshes a watched scanner may miss before it is reported as removed
+const MISSED_REFRESHES: u32 = 2;
+
+#[derive(Clone)]
 pub struct ScannerFinder {
-    scanners: Arc<Mutex<Vec<Scanner>>>,
+    backend: Arc<dyn DiscoveryBackend>,
+    discovery_options: DiscoveryOptions,
+    connection_options: ConnectionOptions,
+    capabilities_cache: Option<Arc<CapabilitiesCache>>,
+    lazy: bool,
+    address_family: AddressFamily,
+    prefer_secure: bool,
+}
+
+impl Default for ScannerFinder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ScannerFinder {
     pub fn new() -> ScannerFinder {
+        Self::with_connection_options(ConnectionOptions::default())
+    }
+
+    /// Uses the certificate options for scanners found via _uscans._tcp.
+    /// Scheme and port are taken from the announcements.
+    pub fn with_connection_options(connection_options: ConnectionOptions) -> ScannerFinder {
         ScannerFinder {
-            scanners: Arc::new(Mutex::new(vec![])),
+            backend: default_backend(),
+            discovery_options: DiscoveryOptions::default(),
+            connection_options,
+            capabilities_cache: None,
+            lazy: false,
+            address_family: AddressFamily::Any,
+            prefer_secure: false,
         }
     }
 
-    fn scanner_found(&self, name: &str) -> bool {
-        let scanners = self.scanners.lock().unwrap();
-        for scanner in scanners.iter() {
-            if scanner.base_url.contains(name) || scanner.device_name.contains(name) {
-                return true;
+    /// Uses a different mDNS implementation than the one selected by cargo
+    /// features.
+    pub fn backend(mut self, backend: Arc<dyn DiscoveryBackend>) -> ScannerFinder {
+        self.backend = backend;
+        self
+    }
+
+    /// Timeout, stop conditions and filter for discovery.
+    pub fn discovery_options(mut self, discovery_options: DiscoveryOptions) -> ScannerFinder {
+        self.discovery_options = discovery_options;
+        self
+    }
+
+    /// Takes capabilities of known scanners from the cache instead of
+    /// fetching them from every scanner found.
+    pub fn capabilities_cache(mut self, cache: Arc<CapabilitiesCache>) -> ScannerFinder {
+        self.capabilities_cache = Some(cache);
+        self
+    }
+
+    /// Does not fetch capabilities during discovery at all, see
+    /// `ScannerBuilder::lazy()`.
+    pub fn lazy(mut self, lazy: bool) -> ScannerFinder {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Connects to scanners with addresses of the given family where known.
+    pub fn address_family(mut self, address_family: AddressFamily) -> ScannerFinder {
+        self.address_family = address_family;
+        self
+    }
+
+    /// Whether to use HTTPS for scanners that offer both HTTP and HTTPS.
+    /// Disabled by default, as most scanners only have a self-signed
+    /// certificate that `ConnectionOptions` would have to accept.
+    pub fn prefer_secure(mut self, prefer_secure: bool) -> ScannerFinder {
+        self.prefer_secure = prefer_secure;
+        self
+    }
+
+    /// Discovers scanners and connects to them. With a name, only matching
+    /// scanners are returned, and discovery stops as soon as one is found.
+    pub fn find(&mut self, name: Option<&str>) -> Result<Vec<Scanner>, ScannerError> {
+        let mut options = self.discovery_options.clone();
+        if let Some(name) = name {
+            let name = name.to_string();
+            let filter = options.filter.take();
+            options.filter = Some(Arc::new(move |service| {
+                service.matches(&name) && filter.as_ref().is_none_or(|filter| filter(service))
+            }));
+            options.stop_on_first_match = true;
+        }
+
+        let services = self.discover_with(&options)?;
+        let scanners = self.resolve(&services);
+        if let Some(name) = name {
+            if scanners.is_empty() {
+                log::info!("No scanner found for name {name}");
+                return Err(ScannerError {
+                    code: ErrorCode::NoScannerFound,
+                    message: name.to_string(),
+                });
             }
         }
 
-        return false;
+        log::info!("Found {} scanners on the network", scanners.len());
+        Ok(scanners)
     }
 
-    pub fn find(&mut self, name: Option<&str>) -> Result<Vec<Scanner>, ScannerError> {
-        let service_type =
-            ServiceType::with_sub_types(&"uscan", &"tcp", vec![]).expect("invalid service type");
-        log::info!("Looking for scanners with {service_type:?}");
-
-        let mut browser = MdnsBrowser::new(service_type);
-        browser.set_service_discovered_callback(Box::new(Self::on_service_discovered));
-        browser.set_context(Box::new(Arc::clone(&self.scanners)));
-
-        let event_loop = browser.browse_services()?;
-        let timeout = Duration::from_secs(5);
-        let end_time = Instant::now() + timeout;
-        while Instant::now() < end_time {
-            log::info!("Polling for scanners...");
-            event_loop.poll(Duration::from_millis(100)).unwrap();
-
-            if let Some(name) = name {
-                if self.scanner_found(name) {
-                    log::info!("Found scanner for name {name}");
-                    return Ok(self.scanners.lock().unwrap().clone());
-                }
+    /// Collects the eSCL services announced on the network without
+    /// connecting to any of them. There is one entry per device.
+    pub fn discover(&mut self) -> Result<Vec<DiscoveredService>, ScannerError> {
+        self.discover_with(&self.discovery_options)
+    }
+
+    /// Discovers scanners in the background and yields them as they appear.
+    /// The stream ends when the discovery options say so.
+    pub fn stream(&self) -> DiscoveryStream {
+        self.spawn_discovery(None)
+    }
+
+    /// Keeps discovering scanners until the stream is dropped, for
+    /// long-running applications. Scanners that announce leaving the network
+    /// are reported as removed. The timeout and stop conditions of the
+    /// discovery options do not apply.
+    ///
+    /// Backends that report removals, like mdns-sd, keep browsing with a
+    /// single session. The others, like zeroconf and probing, only report
+    /// what they found, so they browse again every `refresh_interval` and
+    /// scanners not found again for two intervals are reported as removed.
+    pub fn watch(&self, refresh_interval: Duration) -> DiscoveryStream {
+        self.spawn_discovery(Some(refresh_interval))
+    }
+
+    /// Watches the scanners on the network until the returned monitor is
+    /// dropped, reporting scanners that appear, disappear or change state.
+    pub fn monitor(&self, options: MonitorOptions) -> ScannerMonitor {
+        ScannerMonitor::start(self, options)
+    }
+
+    /// Connects to the given services concurrently. Services that fail to
+    /// initialize are skipped.
+    pub fn resolve(&self, services: &[DiscoveredService]) -> Vec<Scanner> {
+        let next = AtomicUsize::new(0);
+        let scanners = Mutex::new(vec![]);
+        std::thread::scope(|scope| {
+            for _ in 0..RESOLVE_WORKERS.min(services.len()) {
+                scope.spawn(|| loop {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(service) = services.get(idx) else {
+                        break;
+                    };
+
+                    match self.make_scanner(service) {
+                        Ok(scanner) => scanners.lock().unwrap().push((idx, scanner)),
+                        Err(err) => log::warn!(
+                            "Failed to initialize scanner {}: {err}",
+                            service.device_name()
+                        ),
+                    }
+                });
             }
+        });
+
+        // Keep the order of discovery
+        let mut scanners = scanners.into_inner().unwrap();
+        scanners.sort_by_key(|(idx, _)| *idx);
+        scanners.into_iter().map(|(_, scanner)| scanner).collect()
+    }
+
+    /// Connects to a single discovered service.
+    pub fn make_scanner(&self, service: &DiscoveredService) -> Result<Scanner, ScannerError> {
+        let base_url = service
+            .base_url(&self.connection_options, self.address_family)
+            .ok_or_else(|| ScannerError {
+                code: ErrorCode::ProtocolError,
+                message: format!("Service has no resource root (rs): {}", service.name),
+            })?;
+
+        let mut builder = Scanner::builder(&base_url)
+            .device_name(service.device_name())
+            .connection_options(self.connection_options.clone())
+            .advertisement(service.advertisement())
+            .lazy(self.lazy);
+        if let Some(cache) = &self.capabilities_cache {
+            builder = builder.capabilities_cache(Arc::clone(cache));
         }
 
-        if let Some(name) = name {
-            log::info!("No scanner found for name {name}");
-            return Err(ScannerError {
-                code: ErrorCode::NoScannerFound,
-                message: name.to_string(),
-            });
-        } else {
-            let scanners = self.scanners.lock().unwrap();
-            log::info!("Found {} scanners on the network", scanners.len());
-            return Ok(scanners.clone());
-        };
-    }
-
-    fn on_service_discovered(
-        result: zeroconf::Result<ServiceDiscovery>,
-        context: Option<Arc<dyn Any>>,
-    ) {
-        let service = match result {
-            Ok(service) => service,
-            Err(err) => {
-                log::info!("Error during scanner discovery (continuing): {err}");
-                return;
+        let scanner = builder.build()?;
+        log::info!("{:?}", scanner);
+        Ok(scanner)
+    }
+
+    fn discover_with(
+        &self,
+        options: &DiscoveryOptions,
+    ) -> Result<Vec<DiscoveredService>, ScannerError> {
+        let mut browser = Browser::start(self.backend.as_ref())?;
+        let mut matching = vec![];
+        let end_time = Instant::now() + options.timeout;
+        while Instant::now() < end_time && !browser.session.is_finished() {
+            browser.poll()?;
+            matching = self.matching_services(&browser.services, options);
+            if options.is_satisfied(matching.len()) {
+                break;
             }
-        };
-
-        log::info!("Service discovered: {service:?}",);
-        let scanners = context
-            .as_ref()
-            .expect("Context was passed to on_service_discovered")
-            .downcast_ref::<Arc<Mutex<Vec<Scanner>>>>()
-            .expect("context can be downcasted to Arc<Mutex<Vec<Scanner>>>");
-        let mut scanners = scanners.lock().unwrap();
-
-        let txt: &TxtRecord = match service.txt() {
-            Some(txt) => txt,
-            None => {
-                log::warn!("Failed to get txt record for {service:?}");
-                return;
+        }
+
+        if let Some(max_scanners) = options.max_scanners {
+            matching.truncate(max_scanners);
+        }
+        log::info!("Discovered {} eSCL services", matching.len());
+        Ok(matching)
+    }
+
+    // One entry per device, filtered by the discovery options
+    fn matching_services(
+        &self,
+        services: &[DiscoveredService],
+        options: &DiscoveryOptions,
+    ) -> Vec<DiscoveredService> {
+        deduplicate(services.to_vec(), self.prefer_secure)
+            .into_iter()
+            .filter(|service| options.matches(service))
+            .collect()
+    }
+
+    fn spawn_discovery(&self, refresh_interval: Option<Duration>) -> DiscoveryStream {
+        let (sender, receiver) = mpsc::channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let finder = Arc::new(self.clone());
+        let thread_stopped = Arc::clone(&stopped);
+        std::thread::spawn(move || {
+            if let Err(err) = finder.run_discovery(refresh_interval, &sender, &thread_stopped) {
+                let _ = sender.send(DiscoveryEvent::Error(err));
             }
-        };
+        });
 
-        let url_root = match txt.get("rs") {
-            Some(root) => root,
-            None => {
-                log::warn!("Service has no resource root (rs): {service:?}");
-                return;
+        DiscoveryStream::new(receiver, stopped)
+    }
+
+    fn run_discovery(
+        self: &Arc<Self>,
+        refresh_interval: Option<Duration>,
+        sender: &Sender<DiscoveryEvent>,
+        stopped: &AtomicBool,
+    ) -> Result<(), ScannerError> {
+        let options = &self.discovery_options;
+        let mut resolver = ResolvePool::new(Arc::clone(self), sender.clone());
+        let mut known: HashMap<String, DiscoveredService> = HashMap::new();
+        let mut missed_refreshes: HashMap<String, u32> = HashMap::new();
+        let mut browser = Browser::start(self.backend.as_ref())?;
+        let rebrowse = !browser.session.reports_removals();
+        loop {
+            let refresh_start = Instant::now();
+            let mut seen = vec![];
+            while refresh_start.elapsed() < refresh_interval.unwrap_or(options.timeout) {
+                if stopped.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                // A session that finished early, e.g. probing, is not
+                // repeated before the interval is over
+                if browser.session.is_finished() {
+                    if refresh_interval.is_none() {
+                        return Ok(());
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
+                    continue;
+                }
+
+                let removed = browser.poll()?;
+                seen = self.matching_services(&browser.services, options);
+                for service in seen.iter() {
+                    if known.contains_key(&service.device_key()) {
+                        continue;
+                    }
+
+                    known.insert(service.device_key(), service.clone());
+                    if sender
+                        .send(DiscoveryEvent::ServiceFound(service.clone()))
+                        .is_err()
+                    {
+                        return Ok(());
+                    }
+                    resolver.submit(service.clone());
+                }
+
+                // A device is gone once it withdrew all of its services
+                for service in removed {
+                    let key = service.device_key();
+                    if seen.iter().any(|service| service.device_key() == key) {
+                        continue;
+                    }
+                    if let Some(service) = known.remove(&key) {
+                        missed_refreshes.remove(&key);
+                        log::info!("Scanner {} left", service.device_name());
+                        if sender
+                            .send(DiscoveryEvent::ServiceRemoved(service))
+                            .is_err()
+                        {
+                            return Ok(());
+                        }
+                    }
+                }
+
+                if refresh_interval.is_none() && options.is_satisfied(known.len()) {
+                    return Ok(());
+                }
+            }
+
+            if refresh_interval.is_none() {
+                return Ok(());
             }
-        };
-        let device_name = match txt.get("ty") {
-            Some(name) => name,
-            None => {
-                log::warn!("Service has no human-readable device name (ty): {service:?}");
-                return;
+            if !rebrowse {
+                continue;
             }
-        };
 
-        let scanner = match Scanner::new(&device_name, service.host_name(), &url_root) {
-            Ok(scanner) => scanner,
-            Err(err) => {
-                log::warn!("Failed to initialize scanner {device_name}: {err}");
-                return;
+            // Every scanner still around has announced itself again to the
+            // last browser. Announcements get lost now and then, so one
+            // missed refresh is not enough to consider a scanner gone.
+            let mut removed = vec![];
+            for key in known.keys() {
+                if seen.iter().any(|service| &service.device_key() == key) {
+                    missed_refreshes.remove(key);
+                    continue;
+                }
+
+                let missed = missed_refreshes.entry(key.clone()).or_default();
+                *missed += 1;
+                if *missed >= MISSED_REFRESHES {
+                    removed.push(key.clone());
+                }
+            }
+            for key in removed {
+                missed_refreshes.remove(&key);
+                let service = known.remove(&key).expect("removed service is known");
+                log::info!("Scanner {} disappeared", service.device_name());
+                if sender
+                    .send(DiscoveryEvent::ServiceRemoved(service))
+                    .is_err()
+                {
+                    return Ok(());
+                }
             }
-        };
+            browser = Browser::start(self.backend.as_ref())?;
+        }
+    }
+}
 
-        log::info!("{:?}", scanner);
-        scanners.push(scanner);
+// A discovery session and the services it has found so far
+struct Browser {
+    session: Box<dyn DiscoverySession>,
+    services: Vec<DiscoveredService>,
+}
+
+impl Browser {
+    fn start(backend: &dyn DiscoveryBackend) -> Result<Browser, ScannerError> {
+        Ok(Browser {
+            session: backend.browse(&SERVICE_TYPES)?,
+            services: vec![],
+        })
+    }
+
+    // Polls the session once and returns the services withdrawn meanwhile
+    fn poll(&mut self) -> Result<Vec<DiscoveredService>, ScannerError> {
+        log::debug!("Polling for scanners...");
+        let mut removed = vec![];
+        for event in self.session.poll(Duration::from_millis(100))? {
+            match event {
+                BackendEvent::Found(service) => match self
+                    .services
+                    .iter_mut()
+                    .find(|known| known.is_same_service(&service))
+                {
+                    Some(known) => known.merge(service),
+                    None => self.services.push(service),
+                },
+                BackendEvent::Removed { name, service_type } => {
+                    if let Some(idx) = self
+                        .services
+                        .iter()
+                        .position(|known| known.name == name && known.service_type == service_type)
+                    {
+                        removed.push(self.services.remove(idx));
+                    }
+                }
+            }
+        }
+
+        Ok(removed)
+    }
+}
+
+// Connects to the services found by a discovery stream on up to
+// RESOLVE_WORKERS threads, while browsing goes on. The workers exit once the
+// discovery is over and all services are resolved.
+struct ResolvePool {
+    finder: Arc<ScannerFinder>,
+    events: Sender<DiscoveryEvent>,
+    jobs: Sender<DiscoveredService>,
+    queue: Arc<Mutex<Receiver<DiscoveredService>>>,
+    workers: usize,
+}
+
+impl ResolvePool {
+    fn new(finder: Arc<ScannerFinder>, events: Sender<DiscoveryEvent>) -> ResolvePool {
+        let (jobs, queue) = mpsc::channel();
+        ResolvePool {
+            finder,
+            events,
+            jobs,
+            queue: Arc::new(Mutex::new(queue)),
+            workers: 0,
+        }
+    }
+
+    fn submit(&mut self, service: DiscoveredService) {
+        // The pool holds the receiving end itself
+        let _ = self.jobs.send(service);
+        if self.workers >= RESOLVE_WORKERS {
+            return;
+        }
+
+        self.workers += 1;
+        let finder = Arc::clone(&self.finder);
+        let events = self.events.clone();
+        let queue = Arc::clone(&self.queue);
+        std::thread::spawn(move || loop {
+            let Ok(service) = queue.lock().unwrap().recv() else {
+                break;
+            };
+
+            match finder.make_scanner(&service) {
+                Ok(scanner) => {
+                    let event = DiscoveryEvent::ScannerFound {
+                        service,
+                        scanner: Box::new(scanner),
+                    };
+                    if events.send(event).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => log::warn!(
+                    "Failed to initialize scanner {}: {err}",
+                    service.device_name()
+                ),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use crate::{
+        discoveredservice::DiscoveredService,
+        discoverybackend::{BackendEvent, DiscoveryBackend, DiscoverySession},
+        discoveryoptions::DiscoveryOptions,
+        discoverystream::DiscoveryEvent,
+        scannererror::{ErrorCode, ScannerError},
+        scannerfinder::ScannerFinder,
+    };
+
+    // Announces a fixed set of services, one per poll
+    struct FakeBackend(Vec<BackendEvent>);
+
+    struct FakeSession(Vec<BackendEvent>);
+
+    impl DiscoveryBackend for FakeBackend {
+        fn browse(
+            &self,
+            _service_types: &[&str],
+        ) -> Result<Box<dyn DiscoverySession>, ScannerError> {
+            Ok(Box::new(FakeSession(
+                self.0.iter().rev().cloned().collect(),
+            )))
+        }
+    }
+
+    impl DiscoverySession for FakeSession {
+        fn poll(&mut self, _timeout: Duration) -> Result<Vec<BackendEvent>, ScannerError> {
+            Ok(self.0.pop().into_iter().collect())
+        }
+    }
+
+    // Announces a different set of services to every browser, then nothing
+    struct RefreshingBackend(Mutex<Vec<Vec<BackendEvent>>>);
+
+    impl DiscoveryBackend for RefreshingBackend {
+        fn browse(
+            &self,
+            _service_types: &[&str],
+        ) -> Result<Box<dyn DiscoverySession>, ScannerError> {
+            let mut events = self.0.lock().unwrap().pop().unwrap_or_default();
+            events.reverse();
+            Ok(Box::new(FakeSession(events)))
+        }
+    }
+
+    // Like FakeBackend, but reports removals and counts its browsers
+    struct RemovingBackend(Vec<BackendEvent>, AtomicUsize);
+
+    struct RemovingSession(FakeSession);
+
+    impl DiscoveryBackend for RemovingBackend {
+        fn browse(
+            &self,
+            _service_types: &[&str],
+        ) -> Result<Box<dyn DiscoverySession>, ScannerError> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            Ok(Box::new(RemovingSession(FakeSession(
+                self.0.iter().rev().cloned().collect(),
+            ))))
+        }
+    }
+
+    impl DiscoverySession for RemovingSession {
+        fn poll(&mut self, timeout: Duration) -> Result<Vec<BackendEvent>, ScannerError> {
+            self.0.poll(timeout)
+        }
+
+        fn reports_removals(&self) -> bool {
+            true
+        }
+    }
+
+    fn service(name: &str, service_type: &str, address: &str) -> DiscoveredService {
+        DiscoveredService {
+            name: name.to_string(),
+            service_type: service_type.to_string(),
+            host_name: format!("{}.local", name.replace(' ', "-")),
+            port: 80,
+            addresses: vec![address.parse().unwrap()],
+            txt: HashMap::from([
+                ("rs".to_string(), "eSCL".to_string()),
+                ("ty".to_string(), name.to_string()),
+            ]),
+        }
+    }
+
+    fn finder() -> ScannerFinder {
+        let events = vec![
+            BackendEvent::Found(service("Brother MFC", "uscan", "192.168.1.5")),
+            BackendEvent::Found(service("Brother MFC", "uscan", "fe80::1")),
+            BackendEvent::Found(service("Brother MFC", "uscans", "192.168.1.5")),
+            BackendEvent::Found(service("HP LaserJet", "uscan", "192.168.1.6")),
+            BackendEvent::Found(service("Canon Pixma", "uscan", "192.168.1.7")),
+            BackendEvent::Removed {
+                name: "Canon Pixma".to_string(),
+                service_type: "uscan".to_string(),
+            },
+        ];
+
+        ScannerFinder::new()
+            .backend(Arc::new(FakeBackend(events)))
+            .discovery_options(DiscoveryOptions {
+                timeout: Duration::from_millis(100),
+                ..Default::default()
+            })
+            .lazy(true)
+    }
+
+    #[test]
+    fn discover_services() {
+        let services = finder().prefer_secure(true).discover().unwrap();
+        assert!(services.len() == 2);
+        assert!(services[0].name == "Brother MFC" && services[0].is_secure());
+        assert!(services[0].addresses.len() == 2);
+        assert!(services[1].name == "HP LaserJet");
+
+        let mut finder = finder().discovery_options(DiscoveryOptions {
+            max_scanners: Some(1),
+            ..Default::default()
+        });
+        assert!(finder.discover().unwrap().len() == 1);
+    }
+
+    #[test]
+    fn find_by_name() {
+        let scanners = finder().find(Some("HP")).unwrap();
+        assert!(scanners.len() == 1);
+        assert!(scanners[0].device_name == "HP LaserJet");
+        assert!(scanners[0].base_url == "http://HP-LaserJet.local:80/eSCL");
+
+        let err = finder().find(Some("Epson")).unwrap_err();
+        assert!(matches!(err.code, ErrorCode::NoScannerFound));
+    }
+
+    #[test]
+    fn plain_http_by_default() {
+        // Without the certificate options, HTTPS would fail on most scanners
+        let scanners = finder().find(Some("Brother")).unwrap();
+        assert!(scanners.len() == 1);
+        assert!(scanners[0].base_url == "http://Brother-MFC.local:80/eSCL");
+
+        let services = finder().discover().unwrap();
+        assert!(services[0].name == "Brother MFC" && !services[0].is_secure());
+        assert!(services[0].addresses.len() == 2);
+    }
+
+    #[test]
+    fn watch_removals() {
+        let brother = || BackendEvent::Found(service("Brother MFC", "uscan", "192.168.1.5"));
+        let hp = || BackendEvent::Found(service("HP LaserJet", "uscan", "192.168.1.6"));
+        let mut refreshes = vec![
+            vec![brother(), hp()],
+            // Missing once is not enough to be removed
+            vec![hp()],
+            vec![brother(), hp()],
+            vec![
+                brother(),
+                hp(),
+                BackendEvent::Removed {
+                    name: "HP LaserJet".to_string(),
+                    service_type: "uscan".to_string(),
+                },
+            ],
+            vec![],
+            vec![],
+        ];
+        refreshes.reverse();
+
+        let stream = ScannerFinder::new()
+            .backend(Arc::new(RefreshingBackend(Mutex::new(refreshes))))
+            .lazy(true)
+            .watch(Duration::from_millis(300));
+
+        let mut events = vec![];
+        while let Some(event) = stream.next_timeout(Duration::from_secs(3)) {
+            let event = match event {
+                DiscoveryEvent::ServiceFound(service) => format!("found {}", service.name),
+                DiscoveryEvent::ScannerFound { .. } => continue,
+                DiscoveryEvent::ServiceRemoved(service) => format!("removed {}", service.name),
+                DiscoveryEvent::Error(err) => panic!("{err}"),
+            };
+            events.push(event);
+            if events.len() == 4 {
+                break;
+            }
+        }
+        assert!(
+            events
+                == vec![
+                    "found Brother MFC",
+                    "found HP LaserJet",
+                    "removed HP LaserJet",
+                    "removed Brother MFC"
+                ]
+        );
+    }
+
+    #[test]
+    fn watch_with_one_session() {
+        let backend = Arc::new(RemovingBackend(
+            vec![
+                BackendEvent::Found(service("Brother MFC", "uscan", "192.168.1.5")),
+                BackendEvent::Removed {
+                    name: "Brother MFC".to_string(),
+                    service_type: "uscan".to_string(),
+                },
+            ],
+            AtomicUsize::new(0),
+        ));
+        let stream = ScannerFinder::new()
+            .backend(Arc::clone(&backend) as Arc<dyn DiscoveryBackend>)
+            .lazy(true)
+            .watch(Duration::from_millis(100));
+
+        let mut events = vec![];
+        while let Some(event) = stream.next_timeout(Duration::from_millis(500)) {
+            match event {
+                DiscoveryEvent::ServiceFound(service) => {
+                    events.push(format!("found {}", service.name))
+                }
+                DiscoveryEvent::ScannerFound { .. } => {}
+                DiscoveryEvent::ServiceRemoved(service) => {
+                    events.push(format!("removed {}", service.name))
+                }
+                DiscoveryEvent::Error(err) => panic!("{err}"),
+            }
+        }
+        assert!(events == vec!["found Brother MFC", "removed Brother MFC"]);
+        // Not browsed again, which would have found the scanner once more
+        assert!(backend.1.load(Ordering::Relaxed) == 1);
     }
 }
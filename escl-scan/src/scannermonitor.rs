@@ -0,0 +1,414 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    discoverystream::{DiscoveryEvent, DiscoveryStream},
+    scanner::Scanner,
+    scannerfinder::ScannerFinder,
+    structs::ScannerState,
+};
+
+/// How often the monitor looks for changes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonitorOptions {
+    /// Scanners not announced again for two of these intervals are offline,
+    /// see `ScannerFinder::watch()`
+    pub refresh_interval: Duration,
+    /// Interval for checking the status of known scanners
+    pub status_interval: Duration,
+}
+
+impl Default for MonitorOptions {
+    fn default() -> Self {
+        MonitorOptions {
+            refresh_interval: Duration::from_secs(60),
+            status_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A change in the availability of a scanner.
+#[derive(Clone, Debug)]
+pub enum PresenceEvent {
+    /// The scanner was discovered or answers again
+    Online(Box<Scanner>),
+    /// The scanner is not announced anymore or does not answer
+    Offline(Box<Scanner>),
+    /// The scanner reported a different state, e.g. Processing while
+    /// someone else is scanning
+    StateChanged(Box<Scanner>, ScannerState),
+}
+
+// Unreachable scanners would otherwise hold up status checks for the
+// client's default timeout
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Unreachable scanners take until STATUS_TIMEOUT, so several are checked at
+// once, but not one thread per scanner on every check
+const STATUS_WORKERS: usize = 8;
+
+type Callback = Box<dyn Fn(&PresenceEvent) + Send>;
+
+#[derive(Default)]
+struct Subscribers {
+    callbacks: Vec<Callback>,
+    senders: Vec<Sender<PresenceEvent>>,
+}
+
+impl Subscribers {
+    // Callbacks run without the lock, so that they may subscribe themselves
+    fn notify(subscribers: &Mutex<Subscribers>, event: PresenceEvent) {
+        let callbacks = std::mem::take(&mut subscribers.lock().unwrap().callbacks);
+        for callback in callbacks.iter() {
+            callback(&event);
+        }
+
+        let mut subscribers = subscribers.lock().unwrap();
+        let added = std::mem::replace(&mut subscribers.callbacks, callbacks);
+        subscribers.callbacks.extend(added);
+        // Forget about receivers that were dropped
+        subscribers
+            .senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
+
+struct TrackedScanner {
+    scanner: Scanner,
+    // Like the scanner's, but with STATUS_TIMEOUT
+    status_client: reqwest::blocking::Client,
+    online: bool,
+    state: Option<ScannerState>,
+}
+
+impl TrackedScanner {
+    fn new(scanner: Scanner) -> TrackedScanner {
+        let status_client = scanner
+            .connection_options
+            .client_builder()
+            .and_then(|builder| Ok(builder.timeout(STATUS_TIMEOUT).build()?))
+            .unwrap_or_else(|err| {
+                log::warn!("Failed to create status client, using the scanner's: {err}");
+                scanner.client.clone()
+            });
+
+        TrackedScanner {
+            scanner,
+            status_client,
+            online: true,
+            state: None,
+        }
+    }
+}
+
+/// Keeps track of the scanners on the network in the background, see
+/// `ScannerFinder::monitor()`. Monitoring stops when this is dropped.
+pub struct ScannerMonitor {
+    scanners: Arc<Mutex<HashMap<String, TrackedScanner>>>,
+    subscribers: Arc<Mutex<Subscribers>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl ScannerMonitor {
+    pub(crate) fn start(finder: &ScannerFinder, options: MonitorOptions) -> ScannerMonitor {
+        let monitor = ScannerMonitor {
+            scanners: Arc::new(Mutex::new(HashMap::new())),
+            subscribers: Arc::new(Mutex::new(Subscribers::default())),
+            stopped: Arc::new(AtomicBool::new(false)),
+        };
+
+        let stream = finder.watch(options.refresh_interval);
+        let worker = MonitorWorker {
+            scanners: Arc::clone(&monitor.scanners),
+            subscribers: Arc::clone(&monitor.subscribers),
+            stopped: Arc::clone(&monitor.stopped),
+        };
+        std::thread::spawn(move || worker.run(stream, options));
+
+        monitor
+    }
+
+    /// Calls `callback` on every change, from the monitor's thread.
+    pub fn on_event<F>(&self, callback: F)
+    where
+        F: Fn(&PresenceEvent) + Send + 'static,
+    {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .callbacks
+            .push(Box::new(callback));
+    }
+
+    /// Returns a channel receiving every change from now on.
+    pub fn subscribe(&self) -> Receiver<PresenceEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().senders.push(sender);
+        receiver
+    }
+
+    /// Returns the scanners that are currently online.
+    pub fn online_scanners(&self) -> Vec<Scanner> {
+        self.scanners
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|tracked| tracked.online)
+            .map(|tracked| tracked.scanner.clone())
+            .collect()
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ScannerMonitor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+struct MonitorWorker {
+    scanners: Arc<Mutex<HashMap<String, TrackedScanner>>>,
+    subscribers: Arc<Mutex<Subscribers>>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl MonitorWorker {
+    fn run(self, stream: DiscoveryStream, options: MonitorOptions) {
+        let mut stream = Some(stream);
+        let mut last_check = Instant::now();
+        while !self.stopped.load(Ordering::Relaxed) {
+            let wait = Duration::from_millis(500);
+            match &stream {
+                Some(events) => {
+                    if let Some(event) = events.next_timeout(wait) {
+                        if !self.handle_discovery(event) {
+                            stream = None;
+                        }
+                    }
+                }
+                // Without discovery, known scanners are still watched
+                None => std::thread::sleep(wait),
+            }
+
+            if last_check.elapsed() >= options.status_interval {
+                self.check_status();
+                last_check = Instant::now();
+            }
+        }
+    }
+
+    // Returns false if discovery stopped
+    fn handle_discovery(&self, event: DiscoveryEvent) -> bool {
+        match event {
+            DiscoveryEvent::ServiceFound(_) => {}
+            DiscoveryEvent::ScannerFound { service, scanner } => {
+                log::info!("Scanner {} is online", scanner.device_name);
+                self.scanners.lock().unwrap().insert(
+                    service.device_key(),
+                    TrackedScanner::new((*scanner).clone()),
+                );
+                self.notify(PresenceEvent::Online(scanner));
+            }
+            DiscoveryEvent::ServiceRemoved(service) => {
+                let removed = self.scanners.lock().unwrap().remove(&service.device_key());
+                if let Some(tracked) = removed.filter(|tracked| tracked.online) {
+                    log::info!("Scanner {} is offline", tracked.scanner.device_name);
+                    self.notify(PresenceEvent::Offline(Box::new(tracked.scanner)));
+                }
+            }
+            DiscoveryEvent::Error(err) => {
+                log::error!("Scanner discovery failed: {err}");
+                return false;
+            }
+        }
+
+        true
+    }
+
+    fn check_status(&self) {
+        let scanners: Vec<(String, Scanner)> = self
+            .scanners
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, tracked)| {
+                let scanner = Scanner {
+                    client: tracked.status_client.clone(),
+                    ..tracked.scanner.clone()
+                };
+                (key.clone(), scanner)
+            })
+            .collect();
+
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<(String, Option<ScannerState>)>> = Mutex::new(vec![]);
+        std::thread::scope(|scope| {
+            for _ in 0..STATUS_WORKERS.min(scanners.len()) {
+                scope.spawn(|| loop {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((key, scanner)) = scanners.get(idx) else {
+                        break;
+                    };
+
+                    let state = scanner.get_status().ok();
+                    results.lock().unwrap().push((key.clone(), state));
+                });
+            }
+        });
+
+        for (key, state) in results.into_inner().unwrap() {
+            let event = {
+                let mut scanners = self.scanners.lock().unwrap();
+                // Might have been removed meanwhile
+                let Some(tracked) = scanners.get_mut(&key) else {
+                    continue;
+                };
+                presence_change(tracked, state)
+            };
+
+            if let Some(event) = event {
+                self.notify(event);
+            }
+        }
+    }
+
+    fn notify(&self, event: PresenceEvent) {
+        Subscribers::notify(&self.subscribers, event);
+    }
+}
+
+// Updates a scanner with the result of a status check
+fn presence_change(
+    tracked: &mut TrackedScanner,
+    state: Option<ScannerState>,
+) -> Option<PresenceEvent> {
+    let scanner = Box::new(tracked.scanner.clone());
+    let Some(state) = state else {
+        if !tracked.online {
+            return None;
+        }
+
+        log::info!("Scanner {} does not answer", tracked.scanner.device_name);
+        tracked.online = false;
+        tracked.state = None;
+        return Some(PresenceEvent::Offline(scanner));
+    };
+
+    let previous_state = tracked.state.replace(state.clone());
+    if !tracked.online {
+        log::info!("Scanner {} answers again", tracked.scanner.device_name);
+        tracked.online = true;
+        return Some(PresenceEvent::Online(scanner));
+    }
+
+    match previous_state {
+        Some(previous_state) if previous_state != state => {
+            Some(PresenceEvent::StateChanged(scanner, state))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use crate::{
+        scanner::Scanner,
+        scannermonitor::{presence_change, PresenceEvent, Subscribers, TrackedScanner},
+        structs::ScannerState,
+    };
+
+    fn tracked_scanner() -> TrackedScanner {
+        let scanner = Scanner::builder("http://192.168.1.5:80/eSCL")
+            .device_name("Brother MFC-L2710DW")
+            .lazy(true)
+            .build()
+            .unwrap();
+        TrackedScanner::new(scanner)
+    }
+
+    #[test]
+    fn presence_changes() {
+        let mut tracked = tracked_scanner();
+        assert!(presence_change(&mut tracked, Some(ScannerState::Idle)).is_none());
+        assert!(matches!(
+            presence_change(&mut tracked, Some(ScannerState::Processing)),
+            Some(PresenceEvent::StateChanged(_, ScannerState::Processing))
+        ));
+
+        assert!(matches!(
+            presence_change(&mut tracked, None),
+            Some(PresenceEvent::Offline(_))
+        ));
+        assert!(!tracked.online);
+        assert!(presence_change(&mut tracked, None).is_none());
+
+        assert!(matches!(
+            presence_change(&mut tracked, Some(ScannerState::Idle)),
+            Some(PresenceEvent::Online(_))
+        ));
+        assert!(tracked.online);
+    }
+
+    #[test]
+    fn dropped_subscribers() {
+        let subscribers = Mutex::new(Subscribers::default());
+        let (sender, receiver) = mpsc::channel();
+        subscribers.lock().unwrap().senders.push(sender);
+        let (sender, dropped) = mpsc::channel();
+        subscribers.lock().unwrap().senders.push(sender);
+        drop(dropped);
+
+        let tracked = tracked_scanner();
+        Subscribers::notify(
+            &subscribers,
+            PresenceEvent::Online(Box::new(tracked.scanner)),
+        );
+        assert!(subscribers.lock().unwrap().senders.len() == 1);
+        assert!(matches!(receiver.try_recv(), Ok(PresenceEvent::Online(_))));
+    }
+
+    #[test]
+    fn callbacks_may_subscribe() {
+        let subscribers = Arc::new(Mutex::new(Subscribers::default()));
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(Some(sender));
+        let callback_subscribers = Arc::clone(&subscribers);
+        subscribers
+            .lock()
+            .unwrap()
+            .callbacks
+            .push(Box::new(move |_: &PresenceEvent| {
+                if let Some(sender) = sender.lock().unwrap().take() {
+                    callback_subscribers.lock().unwrap().senders.push(sender);
+                }
+            }));
+
+        let scanner = tracked_scanner().scanner;
+        Subscribers::notify(
+            &subscribers,
+            PresenceEvent::Online(Box::new(scanner.clone())),
+        );
+        Subscribers::notify(&subscribers, PresenceEvent::Offline(Box::new(scanner)));
+        assert!(subscribers.lock().unwrap().callbacks.len() == 1);
+        assert!(matches!(receiver.try_recv(), Ok(PresenceEvent::Online(_))));
+        assert!(matches!(receiver.try_recv(), Ok(PresenceEvent::Offline(_))));
+    }
+}
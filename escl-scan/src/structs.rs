@@ -10,14 +10,183 @@ use std::fmt::Display;
 
 use serde::{Deserialize, Serialize};
 
+// eSCL values are open-ended: vendors add their own, and newer protocol
+// versions add more. Unknown values end up in Other instead of failing
+// deserialization.
+macro_rules! escl_value_enum {
+    ($(#[$meta:meta])* $name:ident { $($(#[$variant_meta:meta])* $variant:ident => $value:literal,)+ }) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        #[serde(from = "String", into = "String")]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)+
+            Other(String),
+        }
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $($name::$variant => $value,)+
+                    $name::Other(value) => value,
+                }
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(value: &str) -> Self {
+                match value {
+                    $($value => $name::$variant,)+
+                    _ => $name::Other(value.to_string()),
+                }
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(value: String) -> Self {
+                value.as_str().into()
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> Self {
+                match value {
+                    $name::Other(value) => value,
+                    _ => value.as_str().to_string(),
+                }
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.as_str())
+            }
+        }
+    };
+}
+
+escl_value_enum!(ColorMode {
+    BlackAndWhite1 => "BlackAndWhite1",
+    Grayscale8 => "Grayscale8",
+    Grayscale16 => "Grayscale16",
+    Rgb24 => "RGB24",
+    Rgb48 => "RGB48",
+});
+
+escl_value_enum!(InputSource {
+    Platen => "Platen",
+    Feeder => "Feeder",
+    Camera => "Camera",
+});
+
+escl_value_enum!(
+    /// MIME type of the scanned document
+    DocumentFormat {
+        Pdf => "application/pdf",
+        Jpeg => "image/jpeg",
+        Png => "image/png",
+        Tiff => "image/tiff",
+    }
+);
+
+impl DocumentFormat {
+    /// Usual file name extension, "bin" for unknown formats
+    pub fn file_extension(&self) -> &str {
+        match self {
+            DocumentFormat::Pdf => "pdf",
+            DocumentFormat::Jpeg => "jpg",
+            DocumentFormat::Png => "png",
+            DocumentFormat::Tiff => "tiff",
+            DocumentFormat::Other(_) => "bin",
+        }
+    }
+
+    fn matches_extension(&self, extension: &str) -> bool {
+        let extension = extension.to_ascii_lowercase();
+        match self {
+            DocumentFormat::Jpeg => extension == "jpg" || extension == "jpeg",
+            DocumentFormat::Tiff => extension == "tiff" || extension == "tif",
+            DocumentFormat::Other(_) => true,
+            _ => extension == self.file_extension(),
+        }
+    }
+
+    /// Replaces the extension of the file name if it does not fit this
+    /// format, e.g. because the scanner offered a different one.
+    pub fn file_name(&self, file_name: &str) -> String {
+        let path = std::path::Path::new(file_name);
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if self.matches_extension(extension) => file_name.to_string(),
+            _ => path
+                .with_extension(self.file_extension())
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+}
+
+escl_value_enum!(ContentType {
+    Photo => "Photo",
+    Text => "Text",
+    TextAndPhoto => "TextAndPhoto",
+    LineArt => "LineArt",
+    Magazine => "Magazine",
+    Halftone => "Halftone",
+    Auto => "Auto",
+});
+
+escl_value_enum!(Intent {
+    Document => "Document",
+    TextAndGraphic => "TextAndGraphic",
+    Photo => "Photo",
+    Preview => "Preview",
+    Object => "Object",
+    BusinessCard => "BusinessCard",
+});
+
 #[derive(Clone, Default, Debug, Deserialize)]
 pub struct Platen {
     #[serde(rename = "PlatenInputCaps", default)]
-    pub platen_input_caps: PlatenInputCaps,
+    pub platen_input_caps: InputSourceCaps,
+}
+
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct Adf {
+    #[serde(rename = "AdfSimplexInputCaps", default)]
+    pub adf_simplex_input_caps: Option<InputSourceCaps>,
+    #[serde(rename = "AdfDuplexInputCaps", default)]
+    pub adf_duplex_input_caps: Option<InputSourceCaps>,
+    #[serde(rename = "FeederCapacity", default)]
+    pub feeder_capacity: Option<u16>,
+    #[serde(rename = "AdfOptions", default)]
+    pub adf_options: AdfOptions,
+    #[serde(rename = "Justification", default)]
+    pub justification: Option<Justification>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct AdfOptions {
+    #[serde(rename = "AdfOption", default)]
+    pub options: Vec<String>,
+}
+
+impl AdfOptions {
+    pub fn contains(&self, option: &str) -> bool {
+        self.options.iter().any(|o| o == option)
+    }
 }
 
 #[derive(Clone, Default, Debug, Deserialize)]
-pub struct PlatenInputCaps {
+pub struct Justification {
+    #[serde(rename = "XImagePosition", default)]
+    pub x_image_position: String,
+    #[serde(rename = "YImagePosition", default)]
+    pub y_image_position: String,
+}
+
+/// Capabilities of a single input source, i.e. the platen or one of the
+/// feeder modes. They all share the same structure.
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct InputSourceCaps {
     #[serde(rename = "MinWidth", default)]
     pub min_width: u16,
     #[serde(rename = "MaxWidth", default)]
@@ -30,7 +199,8 @@ pub struct PlatenInputCaps {
     pub max_scan_regions: u16,
     #[serde(rename = "SettingProfiles")]
     pub setting_profiles: SettingProfiles,
-    // TODO: Make SupportedIntents
+    #[serde(rename = "SupportedIntents", default)]
+    pub supported_intents: SupportedIntents,
     #[serde(rename = "MaxOpticalXResolution", default)]
     pub max_optical_xresolution: u16,
     #[serde(rename = "MaxOpticalYResolution", default)]
@@ -45,6 +215,12 @@ pub struct PlatenInputCaps {
     pub risky_bottom_margin: u16,
 }
 
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct SupportedIntents {
+    #[serde(rename = "Intent", default)]
+    pub intents: Vec<Intent>,
+}
+
 #[derive(Clone, Default, Debug, Deserialize)]
 pub struct SettingProfiles {
     #[serde(rename = "$value")]
@@ -55,48 +231,174 @@ pub struct SettingProfiles {
 pub struct SettingProfile {
     #[serde(rename = "ColorModes")]
     pub color_modes: ColorModes,
+    #[serde(rename = "DocumentFormats", default)]
+    pub document_formats: DocumentFormats,
+    #[serde(rename = "SupportedResolutions", default)]
+    pub supported_resolutions: SupportedResolutions,
+    #[serde(rename = "ColorSpaces", default)]
+    pub color_spaces: ColorSpaces,
+    #[serde(rename = "CcdChannels", default)]
+    pub ccd_channels: CcdChannels,
+    #[serde(rename = "BinaryRenderings", default)]
+    pub binary_renderings: BinaryRenderings,
+}
+
+impl SettingProfile {
+    pub fn supports_resolution(&self, x_resolution: u16, y_resolution: u16) -> bool {
+        let resolutions = &self.supported_resolutions;
+        if let Some(discrete_resolutions) = &resolutions.discrete_resolutions {
+            if discrete_resolutions.entries.iter().any(|resolution| {
+                resolution.x_resolution == x_resolution && resolution.y_resolution == y_resolution
+            }) {
+                return true;
+            }
+        }
+
+        if let Some(range) = &resolutions.resolution_range {
+            return range.x_resolution_range.contains(x_resolution)
+                && range.y_resolution_range.contains(y_resolution);
+        }
+
+        false
+    }
+
+    pub fn supports_color_mode(&self, color_mode: &ColorMode) -> bool {
+        self.color_modes.entries.contains(color_mode)
+    }
+
+    /// Checks the DocumentFormatExt list, or the DocumentFormat list for
+    /// devices that don't advertise the former.
+    pub fn supports_document_format(&self, document_format: &DocumentFormat) -> bool {
+        self.document_formats
+            .supported_formats()
+            .contains(document_format)
+    }
 }
 
 #[derive(Clone, Default, Debug, Deserialize)]
 pub struct ColorModes {
-    #[serde(rename = "$value")]
+    #[serde(rename = "ColorMode", default)]
     pub entries: Vec<ColorMode>,
 }
 
 #[derive(Clone, Default, Debug, Deserialize)]
-pub struct ColorMode {
-    #[serde(rename = "$value")]
-    pub mode_name: String,
+pub struct DocumentFormats {
+    /// MIME types as understood by eSCL 1.x clients
+    #[serde(rename = "DocumentFormat", default)]
+    pub document_formats: Vec<DocumentFormat>,
+    /// MIME types to be used with scan:DocumentFormatExt (eSCL 2.x)
+    #[serde(rename = "DocumentFormatExt", default)]
+    pub document_format_exts: Vec<DocumentFormat>,
+}
+
+impl DocumentFormats {
+    pub fn supported_formats(&self) -> &Vec<DocumentFormat> {
+        if self.document_format_exts.is_empty() {
+            &self.document_formats
+        } else {
+            &self.document_format_exts
+        }
+    }
 }
 
 #[derive(Clone, Default, Debug, Deserialize)]
-pub struct CompressionFactorSupport {
+pub struct SupportedResolutions {
+    #[serde(rename = "DiscreteResolutions", default)]
+    pub discrete_resolutions: Option<DiscreteResolutions>,
+    #[serde(rename = "ResolutionRange", default)]
+    pub resolution_range: Option<ResolutionRange>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct DiscreteResolutions {
+    #[serde(rename = "DiscreteResolution", default)]
+    pub entries: Vec<DiscreteResolution>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct DiscreteResolution {
+    #[serde(rename = "XResolution", default)]
+    pub x_resolution: u16,
+    #[serde(rename = "YResolution", default)]
+    pub y_resolution: u16,
+}
+
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct ResolutionRange {
+    #[serde(rename = "XResolutionRange", default)]
+    pub x_resolution_range: Range,
+    #[serde(rename = "YResolutionRange", default)]
+    pub y_resolution_range: Range,
+}
+
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct Range {
     #[serde(rename = "Min", default)]
-    pub min: i8,
+    pub min: u16,
     #[serde(rename = "Max", default)]
-    pub max: i8,
+    pub max: u16,
     #[serde(rename = "Normal", default)]
-    pub normal: i8,
+    pub normal: u16,
     #[serde(rename = "Step", default)]
-    pub step: i8,
+    pub step: u16,
+}
+
+impl Range {
+    pub fn contains(&self, value: u16) -> bool {
+        if value < self.min || value > self.max {
+            return false;
+        }
+
+        self.step == 0 || (value - self.min).is_multiple_of(self.step)
+    }
 }
 
 #[derive(Clone, Default, Debug, Deserialize)]
-pub struct SupportedMediaTypes {
-    #[serde(rename = "MediaType", default)]
-    pub media_types: Vec<String>,
+pub struct ColorSpaces {
+    #[serde(rename = "ColorSpace", default)]
+    pub color_spaces: Vec<String>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct CcdChannels {
+    #[serde(rename = "CcdChannel", default)]
+    pub ccd_channels: Vec<String>,
 }
 
 #[derive(Clone, Default, Debug, Deserialize)]
-pub struct SharpenSupport {
+pub struct BinaryRenderings {
+    #[serde(rename = "BinaryRendering", default)]
+    pub binary_renderings: Vec<String>,
+}
+
+/// Supported values of one of the image adjustment settings, i.e.
+/// BrightnessSupport, SharpenSupport etc.
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct AdjustmentSupport {
     #[serde(rename = "Min", default)]
-    pub min: i8,
+    pub min: i32,
     #[serde(rename = "Max", default)]
-    pub max: i8,
+    pub max: i32,
     #[serde(rename = "Normal", default)]
-    pub normal: i8,
+    pub normal: i32,
     #[serde(rename = "Step", default)]
-    pub step: i8,
+    pub step: i32,
+}
+
+impl AdjustmentSupport {
+    pub fn contains(&self, value: i32) -> bool {
+        if value < self.min || value > self.max {
+            return false;
+        }
+
+        self.step <= 0 || (value - self.min) % self.step == 0
+    }
+}
+
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct SupportedMediaTypes {
+    #[serde(rename = "MediaType", default)]
+    pub media_types: Vec<String>,
 }
 
 #[derive(Clone, Default, Debug, Deserialize)]
@@ -115,15 +417,108 @@ pub struct ScannerCapabilities {
     pub icon_uri: String,
     #[serde(rename = "Platen", default)]
     pub platen: Platen,
+    #[serde(rename = "Adf", default)]
+    pub adf: Option<Adf>,
+    #[serde(rename = "BrightnessSupport", default)]
+    pub brightness_support: Option<AdjustmentSupport>,
     #[serde(rename = "CompressionFactorSupport", default)]
-    pub compression_factor_support: CompressionFactorSupport,
+    pub compression_factor_support: Option<AdjustmentSupport>,
+    #[serde(rename = "ContrastSupport", default)]
+    pub contrast_support: Option<AdjustmentSupport>,
+    #[serde(rename = "GammaSupport", default)]
+    pub gamma_support: Option<AdjustmentSupport>,
+    #[serde(rename = "HighlightSupport", default)]
+    pub highlight_support: Option<AdjustmentSupport>,
+    #[serde(rename = "NoiseRemovalSupport", default)]
+    pub noise_removal_support: Option<AdjustmentSupport>,
+    #[serde(rename = "ShadowSupport", default)]
+    pub shadow_support: Option<AdjustmentSupport>,
+    #[serde(rename = "SharpenSupport", default)]
+    pub sharpen_support: Option<AdjustmentSupport>,
+    #[serde(rename = "ThresholdSupport", default)]
+    pub threshold_support: Option<AdjustmentSupport>,
     #[serde(rename = "SupportedMediaTypes", default)]
     pub supported_media_types: SupportedMediaTypes,
-    #[serde(rename = "SharpenSupport", default)]
-    pub sharpen_support: SharpenSupport,
 }
 
-#[derive(Default, Debug, Deserialize, PartialEq)]
+impl ScannerCapabilities {
+    /// Returns the capabilities for the given input source, if the scanner
+    /// has it. Only the feeder can scan duplex.
+    pub fn input_caps(&self, input_source: &InputSource, duplex: bool) -> Option<&InputSourceCaps> {
+        match input_source {
+            InputSource::Platen if !duplex => Some(&self.platen.platen_input_caps),
+            InputSource::Feeder => {
+                let adf = self.adf.as_ref()?;
+                if !duplex {
+                    return adf.adf_simplex_input_caps.as_ref();
+                }
+
+                // Some scanners only announce duplex support as an AdfOption
+                // and share the simplex caps for it.
+                match &adf.adf_duplex_input_caps {
+                    Some(duplex_caps) => Some(duplex_caps),
+                    None if adf.adf_options.contains("Duplex") => {
+                        adf.adf_simplex_input_caps.as_ref()
+                    }
+                    None => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the supported range of the given image adjustment, or None if
+    /// the scanner does not offer it.
+    pub fn adjustment_support(&self, adjustment: ImageAdjustment) -> Option<&AdjustmentSupport> {
+        match adjustment {
+            ImageAdjustment::Brightness => self.brightness_support.as_ref(),
+            ImageAdjustment::CompressionFactor => self.compression_factor_support.as_ref(),
+            ImageAdjustment::Contrast => self.contrast_support.as_ref(),
+            ImageAdjustment::Gamma => self.gamma_support.as_ref(),
+            ImageAdjustment::Highlight => self.highlight_support.as_ref(),
+            ImageAdjustment::NoiseRemoval => self.noise_removal_support.as_ref(),
+            ImageAdjustment::Shadow => self.shadow_support.as_ref(),
+            ImageAdjustment::Sharpen => self.sharpen_support.as_ref(),
+            ImageAdjustment::Threshold => self.threshold_support.as_ref(),
+        }
+    }
+}
+
+/// The optional eSCL 2.x image adjustment settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageAdjustment {
+    Brightness,
+    CompressionFactor,
+    Contrast,
+    Gamma,
+    Highlight,
+    NoiseRemoval,
+    Shadow,
+    Sharpen,
+    Threshold,
+}
+
+impl ImageAdjustment {
+    pub const ALL: [ImageAdjustment; 9] = [
+        ImageAdjustment::Brightness,
+        ImageAdjustment::CompressionFactor,
+        ImageAdjustment::Contrast,
+        ImageAdjustment::Gamma,
+        ImageAdjustment::Highlight,
+        ImageAdjustment::NoiseRemoval,
+        ImageAdjustment::Shadow,
+        ImageAdjustment::Sharpen,
+        ImageAdjustment::Threshold,
+    ];
+}
+
+impl Display for ImageAdjustment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", *self)
+    }
+}
+
+#[derive(Clone, Default, Debug, Deserialize, PartialEq)]
 #[serde(rename = "State")]
 pub enum ScannerState {
     Idle,
@@ -140,13 +535,115 @@ impl Display for ScannerState {
     }
 }
 
-#[derive(Default, Debug, Deserialize)]
+escl_value_enum!(AdfState {
+    ScannerAdfLoaded => "ScannerAdfLoaded",
+    ScannerAdfEmpty => "ScannerAdfEmpty",
+    ScannerAdfProcessing => "ScannerAdfProcessing",
+    ScannerAdfJam => "ScannerAdfJam",
+    ScannerAdfDoorOpen => "ScannerAdfDoorOpen",
+    ScannerAdfHatchOpen => "ScannerAdfHatchOpen",
+    ScannerAdfMispick => "ScannerAdfMispick",
+    ScannerAdfMultipickDetected => "ScannerAdfMultipickDetected",
+    ScannerAdfDuplexPageTooShort => "ScannerAdfDuplexPageTooShort",
+    ScannerAdfDuplexPageTooLong => "ScannerAdfDuplexPageTooLong",
+    ScannerAdfInputTrayFailed => "ScannerAdfInputTrayFailed",
+    ScannerAdfInputTrayOverloaded => "ScannerAdfInputTrayOverloaded",
+});
+
+escl_value_enum!(JobState {
+    Pending => "Pending",
+    Processing => "Processing",
+    Completed => "Completed",
+    Canceled => "Canceled",
+    Aborted => "Aborted",
+});
+
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct JobStateReasons {
+    #[serde(rename = "JobStateReason", default)]
+    pub reasons: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct JobInfo {
+    #[serde(rename = "JobUri", deserialize_with = "deserialize_trimmed")]
+    pub job_uri: String,
+    #[serde(rename = "JobUuid", deserialize_with = "deserialize_trimmed")]
+    pub job_uuid: String,
+    /// Seconds since the job was created
+    #[serde(rename = "Age")]
+    pub age: Option<u32>,
+    #[serde(rename = "ImagesCompleted")]
+    pub images_completed: Option<u32>,
+    #[serde(rename = "ImagesToTransfer")]
+    pub images_to_transfer: Option<u32>,
+    #[serde(rename = "JobState")]
+    pub job_state: JobState,
+    #[serde(rename = "JobStateReasons", default)]
+    pub job_state_reasons: JobStateReasons,
+}
+
+impl JobInfo {
+    /// The job's UUID without the "urn:uuid:" prefix.
+    pub fn uuid(&self) -> &str {
+        self.job_uuid
+            .strip_prefix("urn:uuid:")
+            .unwrap_or(&self.job_uuid)
+    }
+}
+
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct Jobs {
+    #[serde(rename = "JobInfo", default)]
+    pub job_infos: Vec<JobInfo>,
+}
+
+#[derive(Clone, Default, Debug, Deserialize)]
 #[serde(rename = "scan:ScannerStatus")]
 pub struct ScannerStatus {
+    #[serde(rename = "Version")]
+    pub version: Option<String>,
     #[serde(rename = "State")]
     pub state: ScannerState,
+    /// Only reported by scanners with a feeder
     #[serde(rename = "AdfState")]
-    pub adf_state: String,
+    pub adf_state: Option<AdfState>,
+    #[serde(rename = "Jobs", default)]
+    pub jobs: Jobs,
+}
+
+impl ScannerStatus {
+    /// Looks up a job by its UUID, with or without the "urn:uuid:" prefix.
+    pub fn job(&self, uuid: &str) -> Option<&JobInfo> {
+        let uuid = uuid.strip_prefix("urn:uuid:").unwrap_or(uuid);
+        self.jobs.job_infos.iter().find(|job| job.uuid() == uuid)
+    }
+}
+
+// Some scanners wrap values like the JobUri in line breaks
+fn deserialize_trimmed<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(String::deserialize(deserializer)?.trim().to_string())
+}
+
+/// Details about the most recently scanned page of a job.
+#[derive(Clone, Default, Debug, Deserialize)]
+#[serde(rename = "scan:ScanImageInfo")]
+pub struct ScanImageInfo {
+    #[serde(rename = "JobUri")]
+    pub job_uri: Option<String>,
+    #[serde(rename = "JobUuid")]
+    pub job_uuid: Option<String>,
+    #[serde(rename = "ActualWidth")]
+    pub actual_width: Option<u32>,
+    #[serde(rename = "ActualHeight")]
+    pub actual_height: Option<u32>,
+    #[serde(rename = "ActualBytesPerLine")]
+    pub actual_bytes_per_line: Option<u32>,
+    #[serde(rename = "BlankPageDetected")]
+    pub blank_page_detected: Option<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -199,41 +696,115 @@ impl ScanRegion {
 pub struct ScanSettings {
     #[serde(rename = "pwg:Version")]
     pub version: String,
+    #[serde(rename = "scan:Intent", skip_serializing_if = "Option::is_none")]
+    pub intent: Option<Intent>,
     #[serde(rename = "pwg:ContentType")]
-    pub content_type: String,
+    pub content_type: ContentType,
     #[serde(rename = "pwg:InputSource")]
-    pub input_source: String,
+    pub input_source: InputSource,
     #[serde(rename = "pwg:ScanRegions")]
     pub scan_regions: ScanRegion,
     #[serde(rename = "scan:ColorMode")]
-    pub color_mode: String,
+    pub color_mode: ColorMode,
     #[serde(rename = "scan:DocumentFormatExt")]
-    pub document_format: String,
+    pub document_format: DocumentFormat,
     #[serde(rename = "scan:FeedDirection")]
-    pub feed_direction: String,
+    pub feed_direction: FeedDirection,
     #[serde(rename = "scan:XResolution")]
     pub x_resolution: i16,
     #[serde(rename = "scan:YResolution")]
     pub y_resolution: i16,
+    #[serde(rename = "scan:Duplex", skip_serializing_if = "Option::is_none")]
+    pub duplex: Option<bool>,
+    #[serde(rename = "scan:Brightness", skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<i32>,
+    #[serde(
+        rename = "scan:CompressionFactor",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub compression_factor: Option<i32>,
+    #[serde(rename = "scan:Contrast", skip_serializing_if = "Option::is_none")]
+    pub contrast: Option<i32>,
+    #[serde(rename = "scan:Gamma", skip_serializing_if = "Option::is_none")]
+    pub gamma: Option<i32>,
+    #[serde(rename = "scan:Highlight", skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<i32>,
+    #[serde(rename = "scan:NoiseRemoval", skip_serializing_if = "Option::is_none")]
+    pub noise_removal: Option<i32>,
+    #[serde(rename = "scan:Shadow", skip_serializing_if = "Option::is_none")]
+    pub shadow: Option<i32>,
+    #[serde(rename = "scan:Sharpen", skip_serializing_if = "Option::is_none")]
+    pub sharpen: Option<i32>,
+    #[serde(rename = "scan:Threshold", skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<i32>,
 }
 
-#[derive(Default, Debug, Deserialize, Serialize)]
-#[serde(rename = "$value")]
-pub enum FeedDirection {
-    LongEdgeFeed,
-    #[default]
-    ShortEdgeFeed,
-}
+impl ScanSettings {
+    /// 300 dpi color JPEG without adjustments, which any scanner should
+    /// accept.
+    pub fn new(input_source: InputSource, scan_regions: ScanRegion) -> ScanSettings {
+        ScanSettings {
+            version: "2.6".to_string(),
+            intent: None,
+            content_type: ContentType::Auto,
+            input_source,
+            scan_regions,
+            color_mode: ColorMode::Rgb24,
+            document_format: DocumentFormat::Jpeg,
+            feed_direction: FeedDirection::ShortEdgeFeed,
+            x_resolution: 300,
+            y_resolution: 300,
+            duplex: None,
+            brightness: None,
+            compression_factor: None,
+            contrast: None,
+            gamma: None,
+            highlight: None,
+            noise_removal: None,
+            shadow: None,
+            sharpen: None,
+            threshold: None,
+        }
+    }
+
+    pub fn adjustment(&self, adjustment: ImageAdjustment) -> Option<i32> {
+        match adjustment {
+            ImageAdjustment::Brightness => self.brightness,
+            ImageAdjustment::CompressionFactor => self.compression_factor,
+            ImageAdjustment::Contrast => self.contrast,
+            ImageAdjustment::Gamma => self.gamma,
+            ImageAdjustment::Highlight => self.highlight,
+            ImageAdjustment::NoiseRemoval => self.noise_removal,
+            ImageAdjustment::Shadow => self.shadow,
+            ImageAdjustment::Sharpen => self.sharpen,
+            ImageAdjustment::Threshold => self.threshold,
+        }
+    }
 
-impl From<FeedDirection> for String {
-    fn from(value: FeedDirection) -> Self {
-        match value {
-            FeedDirection::LongEdgeFeed => "LongEdgeFeed".to_string(),
-            FeedDirection::ShortEdgeFeed => "ShortEdgeFeed".to_string(),
+    pub fn adjustment_mut(&mut self, adjustment: ImageAdjustment) -> &mut Option<i32> {
+        match adjustment {
+            ImageAdjustment::Brightness => &mut self.brightness,
+            ImageAdjustment::CompressionFactor => &mut self.compression_factor,
+            ImageAdjustment::Contrast => &mut self.contrast,
+            ImageAdjustment::Gamma => &mut self.gamma,
+            ImageAdjustment::Highlight => &mut self.highlight,
+            ImageAdjustment::NoiseRemoval => &mut self.noise_removal,
+            ImageAdjustment::Shadow => &mut self.shadow,
+            ImageAdjustment::Sharpen => &mut self.sharpen,
+            ImageAdjustment::Threshold => &mut self.threshold,
         }
     }
 }
 
+escl_value_enum!(
+    #[derive(Default)]
+    FeedDirection {
+        LongEdgeFeed => "LongEdgeFeed",
+        #[default]
+        ShortEdgeFeed => "ShortEdgeFeed",
+    }
+);
+
 #[cfg(test)]
 mod tests {
     use crate::structs::*;
@@ -253,21 +824,164 @@ mod tests {
 
         let result = serde_xml_rs::from_str::<ScannerCapabilities>(&xml);
         if let Err(err) = result {
-            println!("{err}");
-            assert!(false);
-            return;
+            panic!("Failed to parse the reference capabilities: {err}");
         }
 
         assert!(result.is_ok());
         let caps = result.ok().unwrap();
-        let setting_profiles = caps.platen.platen_input_caps.setting_profiles.entries;
+        let setting_profiles = &caps.platen.platen_input_caps.setting_profiles.entries;
         assert!(setting_profiles.len() == 1);
         let platen_profile = setting_profiles.first().unwrap();
         let color_modes = &platen_profile.color_modes.entries;
         assert!(color_modes.len() == 3);
-        assert!(color_modes[0].mode_name == "BlackAndWhite1");
-        assert!(color_modes[1].mode_name == "Grayscale8");
-        assert!(color_modes[2].mode_name == "RGB24");
+        assert!(color_modes[0] == ColorMode::BlackAndWhite1);
+        assert!(color_modes[1] == ColorMode::Grayscale8);
+        assert!(color_modes[2] == ColorMode::Rgb24);
+
+        let document_formats = &platen_profile.document_formats;
+        assert!(document_formats.document_formats == [DocumentFormat::Pdf, DocumentFormat::Jpeg]);
+        assert!(
+            document_formats.document_format_exts == [DocumentFormat::Pdf, DocumentFormat::Jpeg]
+        );
+
+        assert!(
+            caps.platen.platen_input_caps.supported_intents.intents
+                == [
+                    Intent::Document,
+                    Intent::TextAndGraphic,
+                    Intent::Photo,
+                    Intent::Preview
+                ]
+        );
+
+        let resolutions = &platen_profile.supported_resolutions;
+        assert!(resolutions.resolution_range.is_none());
+        let discrete_resolutions = &resolutions.discrete_resolutions.as_ref().unwrap().entries;
+        assert!(discrete_resolutions.len() == 4);
+        for (resolution, dpi) in discrete_resolutions.iter().zip([100, 200, 300, 600]) {
+            assert!(resolution.x_resolution == dpi);
+            assert!(resolution.y_resolution == dpi);
+        }
+
+        assert!(platen_profile.color_spaces.color_spaces == ["CMYK", "YCC", "sRGB"]);
+        assert!(
+            platen_profile.ccd_channels.ccd_channels
+                == ["Red", "Green", "Blue", "NTSC", "GrayCcd", "GrayCcdEmulated"]
+        );
+        assert!(platen_profile.binary_renderings.binary_renderings == ["Halftone", "Threshold"]);
+
+        let adf = caps.adf.as_ref().unwrap();
+        let simplex_caps = adf.adf_simplex_input_caps.as_ref().unwrap();
+        assert!(simplex_caps.max_width == 2550);
+        assert!(simplex_caps.max_height == 4200);
+        assert!(simplex_caps.setting_profiles.entries.len() == 1);
+        assert!(adf.adf_duplex_input_caps.is_none());
+        assert!(adf.feeder_capacity == Some(20));
+        assert!(adf.adf_options.contains("DetectPaperLoaded"));
+        assert!(!adf.adf_options.contains("Duplex"));
+        assert!(adf.justification.is_none());
+
+        assert!(
+            caps.input_caps(&InputSource::Platen, false)
+                .unwrap()
+                .max_height
+                == 3507
+        );
+        assert!(
+            caps.input_caps(&InputSource::Feeder, false)
+                .unwrap()
+                .max_height
+                == 4200
+        );
+        assert!(caps.input_caps(&InputSource::Feeder, true).is_none());
+        assert!(caps.input_caps(&InputSource::Platen, true).is_none());
+        assert!(caps.input_caps(&InputSource::Camera, false).is_none());
+    }
+
+    #[test]
+    fn resolution_range() {
+        let xml = r#"<scan:SupportedResolutions xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03">
+            <scan:ResolutionRange>
+                <scan:XResolutionRange>
+                    <scan:Min>75</scan:Min>
+                    <scan:Max>1200</scan:Max>
+                    <scan:Normal>300</scan:Normal>
+                    <scan:Step>25</scan:Step>
+                </scan:XResolutionRange>
+                <scan:YResolutionRange>
+                    <scan:Min>75</scan:Min>
+                    <scan:Max>600</scan:Max>
+                    <scan:Normal>300</scan:Normal>
+                    <scan:Step>25</scan:Step>
+                </scan:YResolutionRange>
+            </scan:ResolutionRange>
+        </scan:SupportedResolutions>"#;
+
+        let resolutions = serde_xml_rs::from_str::<SupportedResolutions>(xml).unwrap();
+        assert!(resolutions.discrete_resolutions.is_none());
+        let range = resolutions.resolution_range.unwrap();
+        assert!(range.x_resolution_range.min == 75);
+        assert!(range.x_resolution_range.max == 1200);
+        assert!(range.y_resolution_range.max == 600);
+        assert!(range.y_resolution_range.step == 25);
+    }
+
+    #[test]
+    fn adjustment_support() {
+        let xml = r#"<scan:ScannerCapabilities xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03">
+            <scan:BrightnessSupport>
+                <scan:Min>-100</scan:Min>
+                <scan:Max>100</scan:Max>
+                <scan:Normal>0</scan:Normal>
+                <scan:Step>10</scan:Step>
+            </scan:BrightnessSupport>
+        </scan:ScannerCapabilities>"#;
+
+        let caps = serde_xml_rs::from_str::<ScannerCapabilities>(xml).unwrap();
+        let brightness = caps
+            .adjustment_support(ImageAdjustment::Brightness)
+            .unwrap();
+        assert!(brightness.min == -100);
+        assert!(brightness.max == 100);
+        assert!(brightness.contains(-50));
+        assert!(!brightness.contains(55));
+        assert!(!brightness.contains(110));
+        assert!(caps.adjustment_support(ImageAdjustment::Contrast).is_none());
+    }
+
+    #[test]
+    fn escl_value_enums() {
+        assert!(ColorMode::from("RGB24") == ColorMode::Rgb24);
+        assert!(ColorMode::from("RGB96") == ColorMode::Other("RGB96".to_string()));
+        assert!(String::from(DocumentFormat::Pdf) == "application/pdf");
+        assert!(String::from(DocumentFormat::from("image/x-vendor")) == "image/x-vendor");
+    }
+
+    #[test]
+    fn document_format_file_names() {
+        assert!(DocumentFormat::Jpeg.file_name("scan.JPEG") == "scan.JPEG");
+        assert!(DocumentFormat::Jpeg.file_name("scans/scan.pdf") == "scans/scan.jpg");
+        assert!(DocumentFormat::Pdf.file_name("scan") == "scan.pdf");
+        assert!(DocumentFormat::Tiff.file_name("scan.tif") == "scan.tif");
+    }
+
+    #[test]
+    fn scan_settings_serialization() {
+        let mut settings = ScanSettings::new(InputSource::Feeder, ScanRegion::a4_portrait());
+        settings.color_mode = ColorMode::Grayscale8;
+        settings.document_format = DocumentFormat::Pdf;
+        settings.duplex = Some(true);
+        settings.threshold = Some(128);
+
+        let xml = serde_xml_rs::to_string(&settings).unwrap();
+        assert!(!xml.contains("scan:Intent"));
+        assert!(!xml.contains("scan:Brightness"));
+        assert!(xml.contains("<scan:Threshold>128</scan:Threshold>"));
+        assert!(xml.contains("<pwg:InputSource>Feeder</pwg:InputSource>"));
+        assert!(xml.contains("<scan:Duplex>true</scan:Duplex>"));
+        assert!(xml.contains("<scan:ColorMode>Grayscale8</scan:ColorMode>"));
+        assert!(xml.contains("<scan:DocumentFormatExt>application/pdf</scan:DocumentFormatExt>"));
+        assert!(xml.contains("<scan:FeedDirection>ShortEdgeFeed</scan:FeedDirection>"));
     }
 
     #[test]
@@ -286,6 +1000,42 @@ mod tests {
         assert!(result.is_ok());
         let status = result.ok().unwrap();
         assert!(status.state == ScannerState::Idle);
-        // TODO adf_state
+        assert!(status.version.as_deref() == Some("2.63"));
+        assert!(status.adf_state == Some(AdfState::ScannerAdfEmpty));
+
+        let jobs = &status.jobs.job_infos;
+        assert!(jobs.len() == 3);
+        assert!(jobs[0].job_uri == "/eSCL/ScanJobs/71af3a90-8d5b-11ee-80cb-3c2af4493199");
+        assert!(jobs[0].uuid() == "71af3a90-8d5b-11ee-80cb-3c2af4493199");
+        assert!(jobs[0].age == Some(289564));
+        assert!(jobs[0].images_completed == Some(1));
+        assert!(jobs[0].images_to_transfer == Some(1));
+        assert!(jobs[0].job_state == JobState::Completed);
+        assert!(jobs[0].job_state_reasons.reasons == vec!["JobCompletedSuccessfully"]);
+
+        let job = status.job("urn:uuid:253b25c7-8e8d-11ee-8109-3c2af4493199");
+        assert!(job.is_some_and(|job| job.age == Some(285324)));
+        assert!(status.job("253b25c7-8e8d-11ee-8109-3c2af4493199").is_some());
+        assert!(status.job("00000000-0000-0000-0000-000000000000").is_none());
+    }
+
+    #[test]
+    fn scan_image_info() {
+        let xml = r#"<scan:ScanImageInfo xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03" xmlns:pwg="http://www.pwg.org/schemas/2010/12/sm">
+            <pwg:JobUri>/eSCL/ScanJobs/71af3a90-8d5b-11ee-80cb-3c2af4493199</pwg:JobUri>
+            <pwg:JobUuid>urn:uuid:71af3a90-8d5b-11ee-80cb-3c2af4493199</pwg:JobUuid>
+            <scan:ActualWidth>2550</scan:ActualWidth>
+            <scan:ActualHeight>3507</scan:ActualHeight>
+            <scan:ActualBytesPerLine>7650</scan:ActualBytesPerLine>
+        </scan:ScanImageInfo>"#;
+
+        let info: ScanImageInfo = serde_xml_rs::from_str(xml).expect("image info is valid");
+        assert!(
+            info.job_uri.as_deref() == Some("/eSCL/ScanJobs/71af3a90-8d5b-11ee-80cb-3c2af4493199")
+        );
+        assert!(info.actual_width == Some(2550));
+        assert!(info.actual_height == Some(3507));
+        assert!(info.actual_bytes_per_line == Some(7650));
+        assert!(info.blank_page_detected.is_none());
     }
 }
@@ -0,0 +1,239 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// Fixtures shared by the tests of several modules
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+use crate::structs::{DocumentFormat, InputSource, ScanRegion, ScanSettings, ScannerCapabilities};
+
+pub(crate) fn brother_capabilities_xml() -> String {
+    std::fs::read_to_string("../reference/Brother_MFC-2710DW_Capabilities.xml")
+        .expect("reference capabilities are readable")
+}
+
+pub(crate) fn brother_capabilities() -> ScannerCapabilities {
+    serde_xml_rs::from_str(&brother_capabilities_xml()).expect("reference capabilities are valid")
+}
+
+// A4 PDF, supported by the reference scanner from both input sources
+pub(crate) fn scan_settings(input_source: InputSource) -> ScanSettings {
+    ScanSettings {
+        document_format: DocumentFormat::Pdf,
+        ..ScanSettings::new(input_source, ScanRegion::a4_portrait())
+    }
+}
+
+pub(crate) struct TestRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub(crate) struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn ok(body: impl Into<Vec<u8>>) -> TestResponse {
+        TestResponse {
+            status: 200,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn status(status: u16) -> TestResponse {
+        TestResponse {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> TestResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// A minimal HTTP/1.1 server on localhost, answering one request per
+/// connection.
+pub(crate) struct TestServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestServer {
+    pub fn start<F>(mut handler: F) -> TestServer
+    where
+        F: FnMut(&TestRequest) -> TestResponse + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("localhost port is free");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let log = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // Port checks connect without sending anything
+                let Some(request) = read_request(&stream) else {
+                    continue;
+                };
+                log.lock()
+                    .unwrap()
+                    .push(format!("{} {}", request.method, request.path));
+
+                let response = handler(&request);
+                let mut head = format!(
+                    "HTTP/1.1 {} Test\r\nContent-Length: {}\r\nConnection: close\r\n",
+                    response.status,
+                    response.body.len()
+                );
+                for (name, value) in response.headers.iter() {
+                    head.push_str(&format!("{name}: {value}\r\n"));
+                }
+                head.push_str("\r\n");
+                let mut stream = &stream;
+                let _ = stream
+                    .write_all(head.as_bytes())
+                    .and_then(|()| stream.write_all(&response.body));
+            }
+        });
+
+        TestServer { url, requests }
+    }
+
+    /// Method and path of every request received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn read_request(stream: &TcpStream) -> Option<TestRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok().filter(|read| *read > 0)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = TestRequest {
+        method,
+        path,
+        headers,
+        body: vec![],
+    };
+    let length: usize = request
+        .header("Content-Length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body).ok()?;
+    Some(request)
+}
+
+/// Serves the reference scanner at `/eSCL` with a loaded, idle feeder. Each
+/// scan job delivers the next batch of pages.
+pub(crate) fn fake_scanner(jobs: Vec<Vec<Vec<u8>>>) -> TestServer {
+    let capabilities = brother_capabilities_xml();
+    let mut jobs = jobs.into_iter();
+    let mut pages: HashMap<String, Vec<Vec<u8>>> = HashMap::new();
+    let mut job_count = 0;
+
+    TestServer::start(move |request| {
+        let path = request.path.as_str();
+        match (request.method.as_str(), path) {
+            ("GET", "/eSCL/ScannerCapabilities") => TestResponse::ok(capabilities.clone()),
+            ("GET", "/eSCL/ScannerStatus") => TestResponse::ok(FAKE_STATUS),
+            ("POST", "/eSCL/ScanJobs") => {
+                job_count += 1;
+                let job = format!("/eSCL/ScanJobs/{job_count}");
+                let mut batch = jobs.next().unwrap_or_default();
+                batch.reverse();
+                pages.insert(job.clone(), batch);
+                TestResponse::status(201).header("Location", &job)
+            }
+            ("GET", _) if path.ends_with("/NextDocument") => {
+                let job = path.trim_end_matches("/NextDocument");
+                match pages.get_mut(job).and_then(|batch| batch.pop()) {
+                    Some(page) => TestResponse::ok(page),
+                    None => TestResponse::status(404),
+                }
+            }
+            ("GET", _) if path.ends_with("/ScanImageInfo") => TestResponse::ok(FAKE_IMAGE_INFO),
+            ("DELETE", _) if pages.remove(path).is_some() => TestResponse::ok(""),
+            _ => TestResponse::status(404),
+        }
+    })
+}
+
+/// Serves the reference scanner at `/eSCL`, failing to deliver the pages of
+/// any scan job. Jobs can be cancelled.
+pub(crate) fn failing_scanner() -> TestServer {
+    let capabilities = brother_capabilities_xml();
+
+    TestServer::start(move |request| {
+        let path = request.path.as_str();
+        match (request.method.as_str(), path) {
+            ("GET", "/eSCL/ScannerCapabilities") => TestResponse::ok(capabilities.clone()),
+            ("GET", "/eSCL/ScannerStatus") => TestResponse::ok(FAKE_STATUS),
+            ("POST", "/eSCL/ScanJobs") => {
+                TestResponse::status(201).header("Location", "/eSCL/ScanJobs/1")
+            }
+            ("GET", _) if path.ends_with("/NextDocument") => TestResponse::status(500),
+            ("DELETE", "/eSCL/ScanJobs/1") => TestResponse::ok(""),
+            _ => TestResponse::status(404),
+        }
+    })
+}
+
+const FAKE_STATUS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<scan:ScannerStatus xmlns:pwg="http://www.pwg.org/schemas/2010/12/sm"
+    xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03">
+    <pwg:Version>2.63</pwg:Version>
+    <pwg:State>Idle</pwg:State>
+    <scan:AdfState>ScannerAdfLoaded</scan:AdfState>
+</scan:ScannerStatus>"#;
+
+const FAKE_IMAGE_INFO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<scan:ScanImageInfo xmlns:pwg="http://www.pwg.org/schemas/2010/12/sm"
+    xmlns:scan="http://schemas.hp.com/imaging/escl/2011/05/03">
+    <pwg:JobUri>/eSCL/ScanJobs/1</pwg:JobUri>
+    <pwg:JobUuid>1</pwg:JobUuid>
+    <scan:ActualWidth>2480</scan:ActualWidth>
+    <scan:ActualHeight>3507</scan:ActualHeight>
+    <scan:ActualBytesPerLine>7440</scan:ActualBytesPerLine>
+</scan:ScanImageInfo>"#;
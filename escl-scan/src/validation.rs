@@ -0,0 +1,285 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::fmt::Display;
+
+use crate::structs::{
+    ColorMode, DocumentFormat, ImageAdjustment, InputSource, ScanSettings, ScannerCapabilities,
+};
+
+/// A scan setting the scanner would reject, as determined from its
+/// capabilities.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SettingsViolation {
+    InputSourceNotSupported(InputSource),
+    DuplexNotSupported(InputSource),
+    ResolutionNotSupported {
+        x_resolution: i16,
+        y_resolution: i16,
+    },
+    RegionTooWide {
+        width: i32,
+        max_width: u16,
+    },
+    RegionTooTall {
+        height: i32,
+        max_height: u16,
+    },
+    RegionTooNarrow {
+        width: u16,
+        min_width: u16,
+    },
+    RegionTooShort {
+        height: u16,
+        min_height: u16,
+    },
+    ColorModeNotSupported(ColorMode),
+    DocumentFormatNotSupported(DocumentFormat),
+    AdjustmentNotSupported(ImageAdjustment),
+    AdjustmentOutOfRange {
+        adjustment: ImageAdjustment,
+        value: i32,
+        min: i32,
+        max: i32,
+        step: i32,
+    },
+}
+
+impl Display for SettingsViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsViolation::InputSourceNotSupported(input_source) => {
+                write!(f, "Input source {input_source} is not available")
+            }
+            SettingsViolation::DuplexNotSupported(input_source) => {
+                write!(f, "Input source {input_source} cannot scan duplex")
+            }
+            SettingsViolation::ResolutionNotSupported {
+                x_resolution,
+                y_resolution,
+            } => write!(
+                f,
+                "Resolution {x_resolution}x{y_resolution} DPI is not supported"
+            ),
+            SettingsViolation::RegionTooWide { width, max_width } => write!(
+                f,
+                "Scan region extends to {width}, but the maximum width is {max_width}"
+            ),
+            SettingsViolation::RegionTooTall { height, max_height } => write!(
+                f,
+                "Scan region extends to {height}, but the maximum height is {max_height}"
+            ),
+            SettingsViolation::RegionTooNarrow { width, min_width } => write!(
+                f,
+                "Scan region width {width} is below the minimum width of {min_width}"
+            ),
+            SettingsViolation::RegionTooShort { height, min_height } => write!(
+                f,
+                "Scan region height {height} is below the minimum height of {min_height}"
+            ),
+            SettingsViolation::ColorModeNotSupported(color_mode) => {
+                write!(f, "Color mode {color_mode} is not supported")
+            }
+            SettingsViolation::DocumentFormatNotSupported(document_format) => {
+                write!(f, "Document format {document_format} is not supported")
+            }
+            SettingsViolation::AdjustmentNotSupported(adjustment) => {
+                write!(f, "{adjustment} cannot be adjusted on this scanner")
+            }
+            SettingsViolation::AdjustmentOutOfRange {
+                adjustment,
+                value,
+                min,
+                max,
+                step,
+            } => write!(
+                f,
+                "{adjustment} {value} is not supported, use {min} to {max} in steps of {step}"
+            ),
+        }
+    }
+}
+
+impl ScanSettings {
+    /// Checks these settings against the capabilities of the configured
+    /// input source. Returns an empty list if the scanner should accept them.
+    pub fn validate(&self, capabilities: &ScannerCapabilities) -> Vec<SettingsViolation> {
+        let input_caps =
+            match capabilities.input_caps(&self.input_source, self.duplex.unwrap_or(false)) {
+                Some(input_caps) => input_caps,
+                None if capabilities.input_caps(&self.input_source, false).is_some() => {
+                    return vec![SettingsViolation::DuplexNotSupported(
+                        self.input_source.clone(),
+                    )]
+                }
+                None => {
+                    return vec![SettingsViolation::InputSourceNotSupported(
+                        self.input_source.clone(),
+                    )]
+                }
+            };
+
+        let mut violations = vec![];
+        let profiles = &input_caps.setting_profiles.entries;
+
+        let resolution_supported = match (
+            u16::try_from(self.x_resolution),
+            u16::try_from(self.y_resolution),
+        ) {
+            (Ok(x_resolution), Ok(y_resolution)) => profiles
+                .iter()
+                .any(|profile| profile.supports_resolution(x_resolution, y_resolution)),
+            _ => false,
+        };
+        if !resolution_supported {
+            violations.push(SettingsViolation::ResolutionNotSupported {
+                x_resolution: self.x_resolution,
+                y_resolution: self.y_resolution,
+            });
+        }
+
+        let region = &self.scan_regions;
+        let right_edge = region.x_offset as i32 + region.width as i32;
+        if right_edge > input_caps.max_width as i32 {
+            violations.push(SettingsViolation::RegionTooWide {
+                width: right_edge,
+                max_width: input_caps.max_width,
+            });
+        }
+        let bottom_edge = region.y_offset as i32 + region.height as i32;
+        if bottom_edge > input_caps.max_height as i32 {
+            violations.push(SettingsViolation::RegionTooTall {
+                height: bottom_edge,
+                max_height: input_caps.max_height,
+            });
+        }
+        if region.width < input_caps.min_width {
+            violations.push(SettingsViolation::RegionTooNarrow {
+                width: region.width,
+                min_width: input_caps.min_width,
+            });
+        }
+        if region.height < input_caps.min_height {
+            violations.push(SettingsViolation::RegionTooShort {
+                height: region.height,
+                min_height: input_caps.min_height,
+            });
+        }
+
+        if !profiles
+            .iter()
+            .any(|profile| profile.supports_color_mode(&self.color_mode))
+        {
+            violations.push(SettingsViolation::ColorModeNotSupported(
+                self.color_mode.clone(),
+            ));
+        }
+
+        if !profiles
+            .iter()
+            .any(|profile| profile.supports_document_format(&self.document_format))
+        {
+            violations.push(SettingsViolation::DocumentFormatNotSupported(
+                self.document_format.clone(),
+            ));
+        }
+
+        for adjustment in ImageAdjustment::ALL {
+            let value = match self.adjustment(adjustment) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            match capabilities.adjustment_support(adjustment) {
+                Some(support) if !support.contains(value) => {
+                    violations.push(SettingsViolation::AdjustmentOutOfRange {
+                        adjustment,
+                        value,
+                        min: support.min,
+                        max: support.max,
+                        step: support.step,
+                    })
+                }
+                Some(_) => {}
+                None => violations.push(SettingsViolation::AdjustmentNotSupported(adjustment)),
+            }
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::structs::*;
+    use crate::testutil::{brother_capabilities, scan_settings};
+    use crate::validation::SettingsViolation;
+
+    #[test]
+    fn valid_settings() {
+        let caps = brother_capabilities();
+        assert!(scan_settings(InputSource::Platen)
+            .validate(&caps)
+            .is_empty());
+        assert!(scan_settings(InputSource::Feeder)
+            .validate(&caps)
+            .is_empty());
+    }
+
+    #[test]
+    fn invalid_settings() {
+        let caps = brother_capabilities();
+
+        let mut platen_settings = scan_settings(InputSource::Platen);
+        platen_settings.x_resolution = 250;
+        platen_settings.y_resolution = 250;
+        platen_settings.color_mode = ColorMode::Rgb48;
+        platen_settings.document_format = DocumentFormat::Png;
+        platen_settings.scan_regions.height = 4000;
+        platen_settings.scan_regions.width = 8;
+        platen_settings.sharpen = Some(2);
+        assert!(
+            platen_settings.validate(&caps)
+                == vec![
+                    SettingsViolation::ResolutionNotSupported {
+                        x_resolution: 250,
+                        y_resolution: 250
+                    },
+                    SettingsViolation::RegionTooTall {
+                        height: 4000,
+                        max_height: 3507
+                    },
+                    SettingsViolation::RegionTooNarrow {
+                        width: 8,
+                        min_width: 16
+                    },
+                    SettingsViolation::ColorModeNotSupported(ColorMode::Rgb48),
+                    SettingsViolation::DocumentFormatNotSupported(DocumentFormat::Png),
+                    SettingsViolation::AdjustmentNotSupported(ImageAdjustment::Sharpen),
+                ]
+        );
+
+        // The reference scanner only has a simplex feeder
+        let mut duplex_settings = scan_settings(InputSource::Feeder);
+        duplex_settings.duplex = Some(true);
+        assert!(
+            duplex_settings.validate(&caps)
+                == vec![SettingsViolation::DuplexNotSupported(InputSource::Feeder)]
+        );
+
+        // The feeder accepts longer pages than the platen
+        let mut feeder_settings = scan_settings(InputSource::Feeder);
+        feeder_settings.scan_regions.height = 4000;
+        assert!(feeder_settings.validate(&caps).is_empty());
+
+        assert!(
+            scan_settings(InputSource::Camera).validate(&caps)
+                == vec![SettingsViolation::InputSourceNotSupported(
+                    InputSource::Camera
+                )]
+        );
+    }
+}
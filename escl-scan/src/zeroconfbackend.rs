@@ -0,0 +1,117 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{
+    any::Any,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use zeroconf::{
+    browser::TMdnsBrowser, event_loop::TEventLoop, txt_record::TTxtRecord, EventLoop, MdnsBrowser,
+    ServiceDiscovery, ServiceType,
+};
+
+use crate::{
+    discoveredservice::DiscoveredService,
+    discoverybackend::{BackendEvent, DiscoveryBackend, DiscoverySession},
+    scannererror::ScannerError,
+};
+
+// Events reported by the mDNS callback since the last poll
+type BrowserContext = Arc<Mutex<Vec<BackendEvent>>>;
+
+/// Discovers scanners with the system's mDNS daemon, i.e. Avahi or Bonjour.
+/// zeroconf does not report services that disappear.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZeroconfBackend;
+
+impl DiscoveryBackend for ZeroconfBackend {
+    fn browse(&self, service_types: &[&str]) -> Result<Box<dyn DiscoverySession>, ScannerError> {
+        let events: BrowserContext = Arc::new(Mutex::new(vec![]));
+        let mut browsers = vec![];
+        let mut event_loops = vec![];
+        for service_name in service_types {
+            let service_type = ServiceType::with_sub_types(service_name, "tcp", vec![])?;
+            log::info!("Looking for scanners with {service_type:?}");
+
+            let mut browser = MdnsBrowser::new(service_type);
+            browser.set_service_discovered_callback(Box::new(on_service_discovered));
+            browser.set_context(Box::new(Arc::clone(&events)));
+
+            event_loops.push(browser.browse_services()?);
+            browsers.push(browser);
+        }
+
+        Ok(Box::new(ZeroconfSession {
+            events,
+            _browsers: browsers,
+            event_loops,
+        }))
+    }
+}
+
+struct ZeroconfSession {
+    events: BrowserContext,
+    // Browsers stop browsing when dropped, keep them around
+    _browsers: Vec<MdnsBrowser>,
+    event_loops: Vec<EventLoop>,
+}
+
+impl DiscoverySession for ZeroconfSession {
+    fn poll(&mut self, timeout: Duration) -> Result<Vec<BackendEvent>, ScannerError> {
+        let timeout = timeout / self.event_loops.len().max(1) as u32;
+        for event_loop in self.event_loops.iter() {
+            event_loop.poll(timeout)?;
+        }
+
+        Ok(std::mem::take(&mut *self.events.lock().unwrap()))
+    }
+}
+
+// Only records the service, connecting to the scanner here would stall the
+// event loop and make us miss other announcements
+fn on_service_discovered(
+    result: zeroconf::Result<ServiceDiscovery>,
+    context: Option<Arc<dyn Any>>,
+) {
+    let service = match result {
+        Ok(service) => service,
+        Err(err) => {
+            log::info!("Error during scanner discovery (continuing): {err}");
+            return;
+        }
+    };
+
+    log::info!("Service discovered: {service:?}",);
+    let events = context
+        .as_ref()
+        .expect("Context was passed to on_service_discovered")
+        .downcast_ref::<BrowserContext>()
+        .expect("context can be downcasted to BrowserContext");
+
+    events
+        .lock()
+        .unwrap()
+        .push(BackendEvent::Found(DiscoveredService::from(&service)));
+}
+
+impl From<&ServiceDiscovery> for DiscoveredService {
+    fn from(service: &ServiceDiscovery) -> Self {
+        DiscoveredService {
+            name: service.name().clone(),
+            service_type: service.service_type().name().clone(),
+            host_name: service.host_name().clone(),
+            port: *service.port(),
+            addresses: service.address().parse().into_iter().collect(),
+            txt: service
+                .txt()
+                .as_ref()
+                .map(|txt| txt.to_map())
+                .unwrap_or_default(),
+        }
+    }
+}